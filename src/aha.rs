@@ -0,0 +1,698 @@
+use url::form_urlencoded;
+
+use crate::ain::Ain;
+use crate::command::{get_request, Command};
+use crate::device::{parse_device_list, DeviceInfo};
+use crate::error::FritzerError;
+use crate::retry::Repetition;
+use crate::stats::{parse_device_stats, DeviceStats};
+use crate::template::{parse_template_list, Template};
+use crate::transport::Transport;
+
+pub const HOMEAUTOSWITCH_PATH: &str = "/webservices/homeautoswitch.lua";
+
+/// A `switchcmd` of the AHA HTTP interface.
+///
+/// Implementations only describe the command name, its parameters and how to
+/// parse the response. [`execute`] takes care of encoding the request,
+/// trimming the response and turning `inval` into an error.
+pub trait AhaCommand: Command + Send + Sync {
+    type Output: Send;
+
+    const SWITCHCMD: &'static str;
+
+    /// Whether the command only reads. Only idempotent commands are repeated
+    /// after ambiguous failures such as timeouts.
+    const IDEMPOTENT: bool = false;
+
+    /// Whether the command sets a state, which sending it twice sets again
+    /// without further effect. Such commands are also repeated while a DECT
+    /// device is busy.
+    const SETS_STATE: bool = false;
+
+    /// Whether the FRITZ!Box answers with an empty body on success. An empty
+    /// body is then neither an error nor a reason to repeat the command.
+    const EMPTY_RESPONSE: bool = false;
+
+    fn ain(&self) -> Option<&Ain> {
+        None
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    fn parse(&self, body: &str) -> Result<Self::Output, FritzerError>;
+}
+
+/// Builds the URL-encoded query of `command`.
+pub fn query<C: AhaCommand + ?Sized>(command: &C, sid: &str) -> String {
+    encode(C::SWITCHCMD, sid, command.ain(), command.parameters())
+}
+
+fn encode<K: AsRef<str>, V: AsRef<str>>(
+    switchcmd: &str,
+    sid: &str,
+    ain: Option<&Ain>,
+    parameters: impl IntoIterator<Item = (K, V)>,
+) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+
+    query.append_pair("switchcmd", switchcmd);
+    query.append_pair("sid", sid);
+
+    if let Some(ain) = ain {
+        query.append_pair("ain", ain.as_str());
+    }
+
+    for (name, value) in parameters {
+        query.append_pair(name.as_ref(), value.as_ref());
+    }
+
+    query.finish()
+}
+
+/// Converts a status 400 of a command addressing `ain` into
+/// [`FritzerError::InvalidAin`].
+fn invalid_ain(e: FritzerError, ain: Option<&Ain>) -> FritzerError {
+    match (e, ain) {
+        (FritzerError::Status(400), Some(ain)) => FritzerError::InvalidAin(ain.to_string()),
+        (e, _) => e,
+    }
+}
+
+fn repetition<C: AhaCommand + ?Sized>() -> Repetition {
+    match (C::IDEMPOTENT, C::SETS_STATE) {
+        (true, _) => Repetition::Idempotent,
+        (false, true) => Repetition::Busy,
+        (false, false) => Repetition::Unsent,
+    }
+}
+
+pub async fn execute<C: AhaCommand + ?Sized>(
+    transport: &dyn Transport,
+    sid: &str,
+    command: &C,
+) -> Result<C::Output, FritzerError> {
+    let query = query(command, sid);
+    let body = get_request(
+        transport,
+        C::COMMAND_PATH,
+        Some(&query),
+        repetition::<C>(),
+        C::EMPTY_RESPONSE,
+    )
+    .await
+    .map_err(|e| invalid_ain(e, command.ain()))?;
+
+    if body.is_empty() && !C::EMPTY_RESPONSE {
+        return Err(FritzerError::InvalidResponse(format!(
+            "Empty response to {}.",
+            C::SWITCHCMD
+        )));
+    }
+
+    match body.trim_end_matches(['\r', '\n']) {
+        "inval" => match command.ain() {
+            Some(ain) => Err(FritzerError::DeviceNotPresent(ain.to_string())),
+            None => Err(FritzerError::InvalidResponse(format!(
+                "{} returned inval.",
+                C::SWITCHCMD
+            ))),
+        },
+        text => command.parse(text),
+    }
+}
+
+/// A `switchcmd` fritzer does not know. The response is passed through as
+/// is, without interpreting `inval`.
+#[derive(Debug, Clone, Copy)]
+pub struct RawCommand<'a> {
+    pub switchcmd: &'a str,
+    pub ain: Option<&'a Ain>,
+    pub parameters: &'a [(String, String)],
+}
+
+impl RawCommand<'_> {
+    /// AVM names all commands that only read `get...`.
+    pub fn is_read(&self) -> bool {
+        self.switchcmd.starts_with("get")
+    }
+
+    pub fn query(&self, sid: &str) -> String {
+        encode(
+            self.switchcmd,
+            sid,
+            self.ain,
+            self.parameters.iter().map(|(k, v)| (k, v)),
+        )
+    }
+
+    /// Fails with [`FritzerError::InvalidValue`] if the command is empty or
+    /// a parameter would replace `switchcmd`, `sid` or `ain`.
+    pub fn validate(&self) -> Result<(), FritzerError> {
+        if self.switchcmd.is_empty()
+            || !self
+                .switchcmd
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(FritzerError::InvalidValue(format!(
+                "'{}' is no valid switchcmd.",
+                self.switchcmd
+            )));
+        }
+
+        match self
+            .parameters
+            .iter()
+            .find(|(name, _)| ["switchcmd", "sid", "ain"].contains(&name.as_str()))
+        {
+            Some((name, _)) => Err(FritzerError::InvalidValue(format!(
+                "The parameter '{}' cannot be set.",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sends `command`, which has to be validated with [`RawCommand::validate`],
+/// and returns the body of the response. Only reading commands are repeated
+/// after ambiguous failures.
+pub async fn execute_raw(
+    transport: &dyn Transport,
+    sid: &str,
+    command: &RawCommand<'_>,
+) -> Result<String, FritzerError> {
+    let query = command.query(sid);
+
+    get_request(
+        transport,
+        HOMEAUTOSWITCH_PATH,
+        Some(&query),
+        match command.is_read() {
+            true => Repetition::Idempotent,
+            false => Repetition::Unsent,
+        },
+        false,
+    )
+    .await
+    .map_err(|e| invalid_ain(e, command.ain))
+}
+
+/// Indents `body` if it is XML. Returns `None` otherwise.
+pub fn pretty_xml(body: &str) -> Option<String> {
+    if !body.trim_start().starts_with('<') {
+        return None;
+    }
+
+    let reader = xml::EventReader::new_with_config(
+        body.as_bytes(),
+        xml::ParserConfig::new().trim_whitespace(true),
+    );
+    let mut output = Vec::new();
+    let mut writer = xml::EmitterConfig::new()
+        .perform_indent(true)
+        .write_document_declaration(false)
+        .create_writer(&mut output);
+
+    // The reader reports a declaration even if the body has none.
+    let declared = body.trim_start().starts_with("<?xml");
+
+    for event in reader {
+        match event.ok()? {
+            xml::reader::XmlEvent::StartDocument { .. } if !declared => {}
+            event => {
+                if let Some(event) = event.as_writer_event() {
+                    writer.write(event).ok()?;
+                }
+            }
+        }
+    }
+
+    String::from_utf8(output).ok()
+}
+
+pub struct GetSwitchList;
+
+impl Command for GetSwitchList {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for GetSwitchList {
+    type Output = Vec<Ain>;
+
+    const SWITCHCMD: &'static str = "getswitchlist";
+
+    const IDEMPOTENT: bool = true;
+
+    fn parse(&self, body: &str) -> Result<Vec<Ain>, FritzerError> {
+        body.split(',')
+            .filter(|ain| !ain.is_empty())
+            .map(|ain| ain.parse())
+            .collect()
+    }
+}
+
+pub struct GetDeviceListInfos;
+
+impl Command for GetDeviceListInfos {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for GetDeviceListInfos {
+    type Output = Vec<DeviceInfo>;
+
+    const SWITCHCMD: &'static str = "getdevicelistinfos";
+
+    const IDEMPOTENT: bool = true;
+
+    fn parse(&self, body: &str) -> Result<Vec<DeviceInfo>, FritzerError> {
+        parse_device_list(body)
+    }
+}
+
+pub struct GetSwitchName<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for GetSwitchName<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for GetSwitchName<'_> {
+    type Output = String;
+
+    const SWITCHCMD: &'static str = "getswitchname";
+
+    const IDEMPOTENT: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, body: &str) -> Result<String, FritzerError> {
+        Ok(body.to_string())
+    }
+}
+
+fn parse_switch_state(body: &str) -> Result<bool, FritzerError> {
+    match body {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(FritzerError::InvalidResponse(format!(
+            "Unknown switch state '{}'.",
+            body
+        ))),
+    }
+}
+
+pub struct GetSwitchState<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for GetSwitchState<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for GetSwitchState<'_> {
+    type Output = bool;
+
+    const SWITCHCMD: &'static str = "getswitchstate";
+
+    const IDEMPOTENT: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, body: &str) -> Result<bool, FritzerError> {
+        parse_switch_state(body)
+    }
+}
+
+pub struct SetSwitchOn<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for SetSwitchOn<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for SetSwitchOn<'_> {
+    type Output = bool;
+
+    const SWITCHCMD: &'static str = "setswitchon";
+
+    const SETS_STATE: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, body: &str) -> Result<bool, FritzerError> {
+        parse_switch_state(body)
+    }
+}
+
+pub struct SetSwitchOff<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for SetSwitchOff<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for SetSwitchOff<'_> {
+    type Output = bool;
+
+    const SWITCHCMD: &'static str = "setswitchoff";
+
+    const SETS_STATE: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, body: &str) -> Result<bool, FritzerError> {
+        parse_switch_state(body)
+    }
+}
+
+pub struct SetSwitchToggle<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for SetSwitchToggle<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for SetSwitchToggle<'_> {
+    type Output = bool;
+
+    const SWITCHCMD: &'static str = "setswitchtoggle";
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, body: &str) -> Result<bool, FritzerError> {
+        parse_switch_state(body)
+    }
+}
+
+pub struct SetHkrTsoll<'a> {
+    pub ain: &'a Ain,
+    /// See [`Setpoint::to_api`].
+    pub tsoll: u16,
+}
+
+impl Command for SetHkrTsoll<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for SetHkrTsoll<'_> {
+    type Output = ();
+
+    const SWITCHCMD: &'static str = "sethkrtsoll";
+
+    const SETS_STATE: bool = true;
+
+    const EMPTY_RESPONSE: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![("param", self.tsoll.to_string())]
+    }
+
+    fn parse(&self, _body: &str) -> Result<(), FritzerError> {
+        Ok(())
+    }
+}
+
+pub struct SetLevelPercentage<'a> {
+    pub ain: &'a Ain,
+    pub percentage: u8,
+}
+
+impl Command for SetLevelPercentage<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for SetLevelPercentage<'_> {
+    type Output = ();
+
+    const SWITCHCMD: &'static str = "setlevelpercentage";
+
+    const SETS_STATE: bool = true;
+
+    const EMPTY_RESPONSE: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![("level", self.percentage.to_string())]
+    }
+
+    fn parse(&self, _body: &str) -> Result<(), FritzerError> {
+        Ok(())
+    }
+}
+
+pub struct GetBasicDeviceStats<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for GetBasicDeviceStats<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for GetBasicDeviceStats<'_> {
+    type Output = DeviceStats;
+
+    const SWITCHCMD: &'static str = "getbasicdevicestats";
+
+    const IDEMPOTENT: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, body: &str) -> Result<DeviceStats, FritzerError> {
+        parse_device_stats(body)
+    }
+}
+
+pub struct SetName<'a> {
+    pub ain: &'a Ain,
+    pub name: &'a str,
+}
+
+impl Command for SetName<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for SetName<'_> {
+    type Output = ();
+
+    const SWITCHCMD: &'static str = "setname";
+
+    const SETS_STATE: bool = true;
+
+    const EMPTY_RESPONSE: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![("name", self.name.to_string())]
+    }
+
+    fn parse(&self, _body: &str) -> Result<(), FritzerError> {
+        Ok(())
+    }
+}
+
+pub struct GetTemplateListInfos;
+
+impl Command for GetTemplateListInfos {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for GetTemplateListInfos {
+    type Output = Vec<Template>;
+
+    const SWITCHCMD: &'static str = "gettemplatelistinfos";
+
+    const IDEMPOTENT: bool = true;
+
+    fn parse(&self, body: &str) -> Result<Vec<Template>, FritzerError> {
+        parse_template_list(body)
+    }
+}
+
+pub struct ApplyTemplate<'a> {
+    pub ain: &'a Ain,
+}
+
+impl Command for ApplyTemplate<'_> {
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
+}
+
+impl AhaCommand for ApplyTemplate<'_> {
+    type Output = ();
+
+    const SWITCHCMD: &'static str = "applytemplate";
+
+    /// Older firmware answers with an empty body, newer firmware with the
+    /// identifier of the template.
+    const EMPTY_RESPONSE: bool = true;
+
+    fn ain(&self) -> Option<&Ain> {
+        Some(self.ain)
+    }
+
+    fn parse(&self, _body: &str) -> Result<(), FritzerError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::device::Setpoint;
+    use crate::transport::tests::FakeTransport;
+
+    #[test]
+    fn query_should_use_api_form_of_ain() {
+        // Arrange
+        let ain = "grp303E4F-3F7D9BE07".parse().unwrap();
+        let command = GetSwitchName { ain: &ain };
+
+        // Act
+        let query = query(&command, "0123456789abcdef");
+
+        // Assert
+        assert_eq!(
+            "switchcmd=getswitchname&sid=0123456789abcdef&ain=grp303E4F-3F7D9BE07",
+            query
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_should_fail_on_inval() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "inval\n")]);
+        let ain = "087610000434".parse().unwrap();
+        let command = GetSwitchName { ain: &ain };
+
+        // Act
+        let result = execute(&transport, "0123456789abcdef", &command).await;
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::DeviceNotPresent(_))));
+    }
+
+    #[tokio::test]
+    async fn execute_should_return_new_switch_state() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "0\n")]);
+        let ain = "087610000434".parse().unwrap();
+
+        // Act
+        let result = execute(
+            &transport,
+            "0123456789abcdef",
+            &SetSwitchToggle { ain: &ain },
+        )
+        .await;
+
+        // Assert
+        assert!(!result.unwrap());
+        assert_eq!(
+            "GET /webservices/homeautoswitch.lua?switchcmd=setswitchtoggle&sid=0123456789abcdef&ain=087610000434",
+            transport.requests.lock().unwrap()[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_should_accept_empty_response_of_setpoint() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "")]);
+        let ain = "099950000123".parse().unwrap();
+        let command = SetHkrTsoll {
+            ain: &ain,
+            tsoll: Setpoint::Celsius(21.0).to_api().unwrap(),
+        };
+
+        // Act
+        let result = execute(&transport, "0123456789abcdef", &command).await;
+
+        // Assert
+        assert!(result.is_ok());
+        assert_eq!(
+            "GET /webservices/homeautoswitch.lua?switchcmd=sethkrtsoll&sid=0123456789abcdef&ain=099950000123&param=42",
+            transport.requests.lock().unwrap()[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_should_return_empty_list_on_blank_line() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "\n")]);
+
+        // Act
+        let result = execute(&transport, "0123456789abcdef", &GetSwitchList).await;
+
+        // Assert
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn execute_raw_should_pass_body_through() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "inval\n")]);
+        let ain = "087610000434".parse().unwrap();
+        let parameters = vec![("level".to_string(), "a b".to_string())];
+        let command = RawCommand {
+            switchcmd: "setlevel",
+            ain: Some(&ain),
+            parameters: &parameters,
+        };
+
+        // Act
+        let body = execute_raw(&transport, "0123456789abcdef", &command).await;
+
+        // Assert
+        assert_eq!("inval\n", body.unwrap());
+        assert_eq!(
+            "GET /webservices/homeautoswitch.lua?switchcmd=setlevel&sid=0123456789abcdef&ain=087610000434&level=a+b",
+            transport.requests.lock().unwrap()[0]
+        );
+    }
+
+    #[test]
+    fn pretty_xml_should_indent_elements_only() {
+        // Arrange
+        let xml = "<devicelist version=\"1\"><device><name>Kettle</name></device></devicelist>\n";
+
+        // Act
+        let pretty = pretty_xml(xml);
+        let text = pretty_xml("087610000434,087610000435\n");
+
+        // Assert
+        assert_eq!(
+            "<devicelist version=\"1\">\n  <device>\n    <name>Kettle</name>\n  </device>\n</devicelist>",
+            pretty.unwrap()
+        );
+        assert!(text.is_none());
+    }
+}
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::FritzerError;
+
+/// Actor identification number (AIN) of a smart home device, group or template.
+///
+/// Accepts the AIN as shown in the FRITZ!Box UI (`08761 0000434`), as used by
+/// the AHA interface (`087610000434`), with the unit suffix of HAN-FUN devices
+/// (`11630 0123456-1`), as well as group (`grp303E4F-3F7D9BE07`), template
+/// (`tmp303E4F-3F7D9BE07`) and Zigbee (`Z001788011D4B55D3`) identifiers.
+/// [`Ain::as_str`] returns the AHA form for requests. [`fmt::Display`] and
+/// [`Serialize`] both use the UI form, so the output of all formats matches.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ain(String);
+
+const ACTOR_DIGITS: usize = 12;
+const MANUFACTURER_DIGITS: usize = 5;
+/// Zigbee identifiers consist of a `Z` and the hexadecimal IEEE address.
+const ZIGBEE_DIGITS: usize = 16;
+
+impl Ain {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn parse_actor(ain: &str) -> Option<String> {
+        let (digits, unit) = match ain.split_once('-') {
+            Some((digits, unit)) => (digits, Some(unit)),
+            None => (ain, None),
+        };
+
+        if digits.len() != ACTOR_DIGITS || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        match unit {
+            Some(unit) if unit.is_empty() || !unit.bytes().all(|b| b.is_ascii_digit()) => None,
+            Some(unit) => Some(format!("{}-{}", digits, unit)),
+            None => Some(digits.to_string()),
+        }
+    }
+
+    fn parse_identifier(ain: &str) -> Option<String> {
+        let lowercase = ain.to_ascii_lowercase();
+
+        for prefix in ["grp", "tmp"] {
+            if let Some(id) = lowercase.strip_prefix(prefix) {
+                let (first, second) = id.split_once('-')?;
+
+                if is_hex(first) && is_hex(second) {
+                    return Some(format!("{}{}", prefix, id.to_ascii_uppercase()));
+                }
+            }
+        }
+
+        match lowercase.strip_prefix('z') {
+            Some(id) if id.len() == ZIGBEE_DIGITS && is_hex(id) => {
+                Some(format!("Z{}", id.to_ascii_uppercase()))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl FromStr for Ain {
+    type Err = FritzerError;
+
+    fn from_str(ain: &str) -> Result<Self, Self::Err> {
+        let compact: String = ain.split_whitespace().collect();
+
+        Ain::parse_actor(&compact)
+            .or_else(|| Ain::parse_identifier(&compact))
+            .map(Ain)
+            .ok_or_else(|| FritzerError::InvalidAin(ain.to_string()))
+    }
+}
+
+impl fmt::Display for Ain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Ain::parse_actor(&self.0) {
+            Some(_) => write!(
+                f,
+                "{} {}",
+                &self.0[..MANUFACTURER_DIGITS],
+                &self.0[MANUFACTURER_DIGITS..]
+            ),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl AsRef<str> for Ain {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Serialize for Ain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ain {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ain = String::deserialize(deserializer)?;
+
+        ain.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ain_should_normalize_ui_format() {
+        // Arrange
+        let ain = "08761 0000434";
+
+        // Act
+        let result = ain.parse::<Ain>().unwrap();
+
+        // Assert
+        assert_eq!("087610000434", result.as_str());
+        assert_eq!("08761 0000434", result.to_string());
+    }
+
+    #[test]
+    fn ain_should_accept_unit_suffix() {
+        // Arrange
+        let ain = "11630 0123456-1";
+
+        // Act
+        let result = ain.parse::<Ain>().unwrap();
+
+        // Assert
+        assert_eq!("116300123456-1", result.as_str());
+        assert_eq!("11630 0123456-1", result.to_string());
+    }
+
+    #[test]
+    fn ain_should_normalize_group_identifier() {
+        // Arrange
+        let ain = "grp303e4f-3f7d9be07";
+
+        // Act
+        let result = ain.parse::<Ain>().unwrap();
+
+        // Assert
+        assert_eq!("grp303E4F-3F7D9BE07", result.as_str());
+        assert_eq!("grp303E4F-3F7D9BE07", result.to_string());
+    }
+
+    #[test]
+    fn ain_should_normalize_zigbee_identifier() {
+        // Arrange
+        let ain = "z001788011d4b55d3";
+
+        // Act
+        let result = ain.parse::<Ain>().unwrap();
+
+        // Assert
+        assert_eq!("Z001788011D4B55D3", result.as_str());
+    }
+
+    #[test]
+    fn ain_should_serialize_ui_format() {
+        // Arrange
+        let ain = "087610000434".parse::<Ain>().unwrap();
+
+        // Act
+        let json = serde_json::to_string(&ain).unwrap();
+
+        // Assert
+        assert_eq!("\"08761 0000434\"", json);
+    }
+
+    #[test]
+    fn ain_should_reject_garbage() {
+        // Arrange
+        let ains = [
+            "",
+            "kitchen",
+            "08761 000043",
+            "08761 0000434-",
+            "grp-1",
+            "Z1",
+            "zed",
+            "ZDF",
+            "Z001788011D4B55D",
+        ];
+
+        // Act
+        let results: Vec<_> = ains.iter().map(|ain| ain.parse::<Ain>()).collect();
+
+        // Assert
+        assert!(results
+            .iter()
+            .all(|r| matches!(r, Err(FritzerError::InvalidAin(_)))));
+    }
+}
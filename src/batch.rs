@@ -0,0 +1,242 @@
+use libfritzer::{config::Profile, error::FritzerError, output::write_rows};
+use log::{error, info};
+use serde::Serialize;
+use std::{fs, io, path::Path};
+use url::Url;
+
+use crate::shell::{Line, LineCommands, Session};
+use crate::{Args, Commands};
+
+/// A command of a batch with the line it was read from.
+struct Step {
+    line: usize,
+    text: String,
+    command: Result<Commands, String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Failed,
+    /// The line could not be parsed.
+    Invalid,
+    /// A previous line failed.
+    Skipped,
+}
+
+#[derive(Serialize)]
+struct StepRow<'a> {
+    line: usize,
+    command: &'a str,
+    status: Status,
+    error: Option<String>,
+}
+
+/// Removes a comment, which starts with a `#` at the beginning of a word
+/// outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut word_start = true;
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') if word_start => return &line[..i],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+
+        word_start = quote.is_none() && c.is_whitespace();
+    }
+
+    line
+}
+
+/// Parses every command of `input` up to `exit`. Empty lines and comments are
+/// ignored.
+fn parse(input: &str) -> Vec<Step> {
+    let mut steps = Vec::new();
+
+    for (i, text) in input.lines().enumerate() {
+        let text = strip_comment(text).trim();
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let command = match Line::parse(text) {
+            Ok(Line {
+                command: LineCommands::Exit,
+            }) => break,
+            Ok(Line {
+                command: LineCommands::Command(Commands::Shell | Commands::Batch { .. }),
+            }) => Err("The shell and batches cannot be run from a batch.".to_string()),
+            Ok(Line {
+                command: LineCommands::Command(command),
+            }) => Ok(command),
+            Err(message) => Err(message),
+        };
+
+        steps.push(Step {
+            line: i + 1,
+            text: text.to_string(),
+            command,
+        });
+    }
+
+    steps
+}
+
+/// The first line of a rendered clap error without its `error: ` prefix.
+fn summary(message: &str) -> String {
+    let line = message.lines().next().unwrap_or_default();
+
+    line.strip_prefix("error: ").unwrap_or(line).to_string()
+}
+
+fn read_input(file: Option<&Path>) -> Result<String, FritzerError> {
+    match file {
+        Some(path) if path != Path::new("-") => Ok(fs::read_to_string(path)?),
+        _ => Ok(io::read_to_string(io::stdin())?),
+    }
+}
+
+/// Runs the commands of `file`, or stdin, in one [`Session`] and prints a
+/// summary to stderr.
+///
+/// Nothing runs if a line is invalid, and the batch stops at the first
+/// failing command, unless `continue_on_error` is set. Returns the first
+/// error.
+pub async fn run(
+    args: &Args,
+    profile: &Profile,
+    url: &Url,
+    file: Option<&Path>,
+    continue_on_error: bool,
+) -> Result<(), FritzerError> {
+    let from_stdin = file.is_none_or(|path| path == Path::new("-"));
+
+    if from_stdin && args.password_stdin {
+        return Err(FritzerError::InvalidValue(
+            "--password-stdin cannot be used with a batch read from stdin.".to_string(),
+        ));
+    }
+
+    let steps = parse(&read_input(file)?);
+    let invalid = steps.iter().filter(|step| step.command.is_err()).count();
+    let mut first_error = None;
+    let mut statuses = Vec::new();
+
+    for step in &steps {
+        if let Err(message) = &step.command {
+            error!("Line {}: {}", step.line, summary(message));
+        }
+    }
+
+    if invalid > 0 {
+        first_error = Some(FritzerError::InvalidValue(format!(
+            "Invalid lines in the batch: {}.",
+            invalid
+        )));
+    }
+
+    if invalid == 0 || continue_on_error {
+        let session = Session::connect(args, profile, url).await?;
+
+        info!("Running {} commands...", steps.len() - invalid);
+
+        for step in &steps {
+            let command = match &step.command {
+                Ok(command) => command,
+                Err(message) => {
+                    statuses.push((Status::Invalid, Some(summary(message))));
+                    continue;
+                }
+            };
+
+            if first_error.is_some() && !continue_on_error {
+                statuses.push((Status::Skipped, None));
+                continue;
+            }
+
+            match session.execute(command).await {
+                Ok(()) => statuses.push((Status::Ok, None)),
+                Err(e) => {
+                    error!("Line {}: {}", step.line, e);
+                    statuses.push((Status::Failed, Some(e.to_string())));
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+    } else {
+        statuses.extend(steps.iter().map(|step| match &step.command {
+            Ok(_) => (Status::Skipped, None),
+            Err(message) => (Status::Invalid, Some(summary(message))),
+        }));
+    }
+
+    let rows: Vec<StepRow> = steps
+        .iter()
+        .zip(statuses)
+        .map(|(step, (status, error))| StepRow {
+            line: step.line,
+            command: &step.text,
+            status,
+            error,
+        })
+        .collect();
+
+    write_rows(&mut io::stderr().lock(), args.output(profile), &rows)?;
+
+    first_error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_comment_should_ignore_hashes_in_words_and_quotes() {
+        // Arrange
+        let lines = [
+            "# Evening",
+            "switch off 'Office #2' # all of them",
+            "switch on tag:a#b",
+        ];
+
+        // Act
+        let stripped: Vec<&str> = lines.iter().map(|line| strip_comment(line)).collect();
+
+        // Assert
+        assert_eq!(
+            vec!["", "switch off 'Office #2' ", "switch on tag:a#b"],
+            stripped
+        );
+    }
+
+    #[test]
+    fn parse_should_number_lines_and_stop_at_exit() {
+        // Arrange
+        let input =
+            "# Night\n\nswitch off Office*\nthermostat set Meeting\nshell\nexit\nswitch on all\n";
+
+        // Act
+        let steps = parse(input);
+
+        // Assert
+        assert_eq!(
+            vec![3, 4, 5],
+            steps.iter().map(|step| step.line).collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            steps[0].command,
+            Ok(Commands::Switch {
+                command: Some(crate::SwitchCommands::Off { .. }),
+                ..
+            })
+        ));
+        assert!(steps[1].command.is_err());
+        assert!(steps[2].command.is_err());
+    }
+}
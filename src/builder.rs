@@ -0,0 +1,314 @@
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+use crate::bulk::DEFAULT_CONCURRENCY;
+use crate::command::{FritzboxLogin, FritzboxSwitchOperator, Login, SwitchOperator};
+use crate::error::FritzerError;
+use crate::retry::RetryPolicy;
+use crate::tls::{self, Fingerprint};
+use crate::transport::{with_base_path, ReqwestTransport, Transport};
+use crate::Fritzbox;
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_USER_AGENT: &str = concat!("fritzer/", env!("CARGO_PKG_VERSION"));
+
+/// Configures the HTTP client and the command implementations of a
+/// [`Fritzbox`]. Without a timeout, a FRITZ!Box that stops answering would
+/// block forever, so both timeouts are enabled by default.
+pub struct FritzboxBuilder<L = FritzboxLogin, S = FritzboxSwitchOperator> {
+    url: Url,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    proxy: Option<Url>,
+    ca_certificates: Vec<Vec<u8>>,
+    fingerprint: Option<Fingerprint>,
+    accept_invalid_certificates: bool,
+    retry_policy: RetryPolicy,
+    concurrency: usize,
+    transport: Option<Arc<dyn Transport>>,
+    login: L,
+    switch_operator: S,
+}
+
+impl FritzboxBuilder {
+    pub fn new(url: Url) -> FritzboxBuilder {
+        FritzboxBuilder {
+            url: with_base_path(url),
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            timeout: Some(DEFAULT_TIMEOUT),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            ca_certificates: Vec::new(),
+            fingerprint: None,
+            accept_invalid_certificates: false,
+            retry_policy: RetryPolicy::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            transport: None,
+            login: FritzboxLogin,
+            switch_operator: FritzboxSwitchOperator,
+        }
+    }
+}
+
+impl<L, S> FritzboxBuilder<L, S>
+where
+    L: Login + 'static,
+    S: SwitchOperator + 'static,
+{
+    /// Timeout for establishing the connection. `None` waits forever.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Timeout for a whole request including the response body. `None`
+    /// waits forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Sends all requests through the given HTTP proxy.
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Trusts the PEM-encoded CA certificate in addition to the system's
+    /// root certificates.
+    pub fn ca_certificate(mut self, pem: &[u8]) -> Self {
+        self.ca_certificates.push(pem.to_vec());
+        self
+    }
+
+    /// Accepts only the certificate with the given SHA-256 fingerprint. CA
+    /// certificates are ignored then.
+    pub fn pin_certificate(mut self, fingerprint: Fingerprint) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Accepts any certificate. This exposes the SID and the password
+    /// response to anyone who can intercept the connection.
+    pub fn danger_accept_invalid_certificates(mut self, accept: bool) -> Self {
+        self.accept_invalid_certificates = accept;
+        self
+    }
+
+    /// Repeats requests that failed because the FRITZ!Box or a DECT device
+    /// was busy. Use [`RetryPolicy::NONE`] to disable retries.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Maximum number of parallel requests of bulk operations.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Uses `transport` instead of an HTTP client. The URL, timeouts, user
+    /// agent, proxy, TLS settings and retry policy are ignored then.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    pub fn login<T: Login>(self, login: T) -> FritzboxBuilder<T, S> {
+        FritzboxBuilder {
+            url: self.url,
+            connect_timeout: self.connect_timeout,
+            timeout: self.timeout,
+            user_agent: self.user_agent,
+            proxy: self.proxy,
+            ca_certificates: self.ca_certificates,
+            fingerprint: self.fingerprint,
+            accept_invalid_certificates: self.accept_invalid_certificates,
+            retry_policy: self.retry_policy,
+            concurrency: self.concurrency,
+            transport: self.transport,
+            login,
+            switch_operator: self.switch_operator,
+        }
+    }
+
+    pub fn switch_operator<T: SwitchOperator>(self, switch_operator: T) -> FritzboxBuilder<L, T> {
+        FritzboxBuilder {
+            url: self.url,
+            connect_timeout: self.connect_timeout,
+            timeout: self.timeout,
+            user_agent: self.user_agent,
+            proxy: self.proxy,
+            ca_certificates: self.ca_certificates,
+            fingerprint: self.fingerprint,
+            accept_invalid_certificates: self.accept_invalid_certificates,
+            retry_policy: self.retry_policy,
+            concurrency: self.concurrency,
+            transport: self.transport,
+            login: self.login,
+            switch_operator,
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Fails with [`FritzerError::Http`] or [`FritzerError::Tls`] if the
+    /// HTTP client cannot be created, e.g. because of an invalid proxy.
+    pub fn build(self) -> Result<Fritzbox<L, S>, FritzerError> {
+        let transport = match &self.transport {
+            Some(transport) => transport.clone(),
+            None => {
+                let client = self.client_builder()?.build()?;
+
+                Arc::new(
+                    ReqwestTransport::with_client(self.url.clone(), client)
+                        .with_retry_policy(self.retry_policy),
+                )
+            }
+        };
+
+        Ok(Fritzbox {
+            session_info: None,
+
+            transport,
+            login: Arc::new(self.login),
+            switch_operator: Arc::new(self.switch_operator),
+            concurrency: self.concurrency,
+        })
+    }
+
+    /// Fetches the fingerprint of the certificate the FRITZ!Box presents,
+    /// without verifying it, so that it can be pinned (trust on first use).
+    pub async fn fetch_fingerprint(&self) -> Result<Fingerprint, FritzerError> {
+        if self.url.scheme() != "https" {
+            return Err(FritzerError::Tls(format!(
+                "{} does not use HTTPS.",
+                self.url
+            )));
+        }
+
+        // Pinned fingerprints and CA certificates would reject the very
+        // certificate to fetch, e.g. after the FRITZ!Box renewed it.
+        let client = self
+            .base_client_builder()?
+            .danger_accept_invalid_certs(true)
+            .tls_info(true)
+            .build()?;
+        let response = client.get(self.url.clone()).send().await?;
+
+        response
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+            .map(Fingerprint::of)
+            .ok_or_else(|| FritzerError::Tls("The FRITZ!Box sent no certificate.".to_string()))
+    }
+
+    /// The client without any TLS settings.
+    fn base_client_builder(&self) -> Result<reqwest::ClientBuilder, FritzerError> {
+        let mut client = reqwest::Client::builder().user_agent(self.user_agent.as_str());
+
+        if let Some(timeout) = self.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+
+        if let Some(proxy) = &self.proxy {
+            client = client.proxy(reqwest::Proxy::all(proxy.clone())?);
+        }
+
+        Ok(client)
+    }
+
+    fn client_builder(&self) -> Result<reqwest::ClientBuilder, FritzerError> {
+        let mut client = self.base_client_builder()?;
+
+        if self.accept_invalid_certificates {
+            return Ok(client.danger_accept_invalid_certs(true));
+        }
+
+        if let Some(fingerprint) = self.fingerprint {
+            return Ok(client.use_preconfigured_tls(tls::pinned_client_config(fingerprint)));
+        }
+
+        for pem in &self.ca_certificates {
+            let certificate = reqwest::Certificate::from_pem(pem)
+                .map_err(|e| FritzerError::Tls(format!("Invalid CA certificate: {}", e)))?;
+
+            client = client.add_root_certificate(certificate);
+        }
+
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fritzbox_builder_should_retain_base_path() {
+        // Arrange
+        let url = Url::parse("https://gateway.example/fritz").unwrap();
+
+        // Act
+        let builder = FritzboxBuilder::new(url);
+
+        // Assert
+        assert_eq!("https://gateway.example/fritz/", builder.url().as_str());
+    }
+
+    #[test]
+    fn fritzbox_builder_build_should_fail_on_invalid_ca_certificate() {
+        // Arrange
+        let url = Url::parse("https://fritz.box").unwrap();
+
+        // Act
+        let result = FritzboxBuilder::new(url)
+            .ca_certificate(b"no certificate")
+            .build();
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::Tls(_))));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_builder_fetch_fingerprint_should_ignore_tls_settings() {
+        // Arrange
+        let url = Url::parse("https://127.0.0.1:1").unwrap();
+        let builder = FritzboxBuilder::new(url).ca_certificate(b"no certificate");
+
+        // Act
+        let result = builder.fetch_fingerprint().await;
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::Http(_))));
+    }
+
+    #[test]
+    fn fritzbox_builder_build_should_fail_on_invalid_proxy() {
+        // Arrange
+        let url = Url::parse("http://fritz.box").unwrap();
+        let proxy = Url::parse("unknown://proxy").unwrap();
+
+        // Act
+        let result = FritzboxBuilder::new(url).proxy(proxy).build();
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::Http(_))));
+    }
+}
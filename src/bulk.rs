@@ -0,0 +1,109 @@
+use futures::stream::{self, StreamExt};
+use std::future::Future;
+
+use crate::ain::Ain;
+use crate::error::FritzerError;
+
+/// The FRITZ!Box answers only a few requests at a time and queues DECT
+/// commands, so more parallel requests would only wait or fail as busy.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Result of an operation on one device of a bulk operation.
+#[derive(Debug)]
+pub struct BulkResult<T> {
+    pub ain: Ain,
+    pub result: Result<T, FritzerError>,
+}
+
+/// Runs `operation` for every AIN with at most `concurrency` operations at a
+/// time. Failures do not stop the remaining operations. The results are in
+/// the order of `ains`.
+pub async fn run<'a, T, F, Fut>(
+    ains: &'a [Ain],
+    concurrency: usize,
+    operation: F,
+) -> Vec<BulkResult<T>>
+where
+    F: Fn(&'a Ain) -> Fut,
+    Fut: Future<Output = Result<T, FritzerError>>,
+{
+    stream::iter(ains)
+        .map(|ain| {
+            let operation = &operation;
+
+            async move {
+                BulkResult {
+                    ain: ain.clone(),
+                    result: operation(ain).await,
+                }
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn ains(count: u64) -> Vec<Ain> {
+        (1..=count)
+            .map(|i| format!("{:012}", 87610000000 + i).parse().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn run_should_collect_results_of_all_devices_in_order() {
+        // Arrange
+        let ains = ains(3);
+
+        // Act
+        let results = run(&ains, 2, |ain| async move {
+            match ain.as_str() {
+                "087610000002" => Err(FritzerError::DeviceNotPresent(ain.to_string())),
+                _ => Ok(ain.as_str().to_string()),
+            }
+        })
+        .await;
+
+        // Assert
+        assert_eq!(3, results.len());
+        assert_eq!(
+            ains,
+            results.iter().map(|r| r.ain.clone()).collect::<Vec<_>>()
+        );
+        assert!(results[0].result.is_ok());
+        assert!(matches!(
+            results[1].result,
+            Err(FritzerError::DeviceNotPresent(_))
+        ));
+        assert!(results[2].result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_should_limit_concurrency() {
+        // Arrange
+        let ains = ains(8);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+
+        // Act
+        run(&ains, 3, |_| async {
+            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+
+            max_running.fetch_max(now_running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+
+            Ok(())
+        })
+        .await;
+
+        // Assert
+        assert_eq!(3, max_running.load(Ordering::SeqCst));
+    }
+}
@@ -1,59 +1,81 @@
 use async_trait::async_trait;
-use reqwest::header::CONTENT_TYPE;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_xml_rs::from_str;
-use std::error::Error;
-use url::Url;
+use url::form_urlencoded;
 
-use crate::connection::SessionInfo;
+use crate::aha::{
+    self, ApplyTemplate, GetBasicDeviceStats, GetDeviceListInfos, GetSwitchList, GetSwitchName,
+    GetSwitchState, GetTemplateListInfos, RawCommand, SetHkrTsoll, SetLevelPercentage, SetName,
+    SetSwitchOff, SetSwitchOn, SetSwitchToggle, HOMEAUTOSWITCH_PATH,
+};
+use crate::ain::Ain;
 
-async fn get_request(client: &reqwest::Client, url: &Url) -> Result<String, Box<dyn Error>> {
-    let request = client.get(url.as_str()).build().unwrap();
-    let res = client.execute(request).await?;
+use crate::connection::SessionInfo;
+use crate::device::{DeviceInfo, Setpoint};
+use crate::error::FritzerError;
+use crate::retry::{retry, Repetition};
+use crate::stats::DeviceStats;
+use crate::template::Template;
+use crate::transport::{Response, Transport};
 
-    Ok(res.text().await?)
+fn into_body(response: Response) -> Result<String, FritzerError> {
+    match response.status {
+        403 => Err(FritzerError::NotConnected),
+        _ if response.is_success() => Ok(response.body),
+        status => Err(FritzerError::Status(status)),
+    }
 }
 
-async fn get_request_with_command_path(
-    client: &reqwest::Client,
-    url: &Url,
-    command_path: &str,
-) -> Result<String, Box<dyn Error>> {
-    let request_url = url.join(command_path).unwrap();
+fn form(pairs: &[(&str, &str)]) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
 
-    get_request(client, &request_url).await
+fn parse_session_info(response: &str) -> Result<Option<SessionInfo>, FritzerError> {
+    Ok(Some(from_str::<SessionInfo>(response)?))
 }
 
-async fn get_request_with_query(
-    client: &reqwest::Client,
-    url: &Url,
+/// Sends a GET request, repeating it according to the retry policy of the
+/// transport after the failures allowed by `repetition`. Empty bodies are
+/// not repeated if `empty_response` marks them as valid.
+pub(crate) async fn get_request(
+    transport: &dyn Transport,
     command_path: &str,
-    query: &str,
-) -> Result<String, Box<dyn Error>> {
-    let mut request_url = url.join(command_path).unwrap();
+    query: Option<&str>,
+    repetition: Repetition,
+    empty_response: bool,
+) -> Result<String, FritzerError> {
+    let description = format!("GET {}", command_path);
+    let response = retry(
+        &transport.retry_policy(),
+        &description,
+        repetition,
+        empty_response,
+        || transport.get(command_path, query),
+    )
+    .await?;
 
-    request_url.set_query(Some(query));
-
-    get_request(client, &request_url).await
+    into_body(response)
 }
 
-async fn post_request(
-    client: &reqwest::Client,
-    url: &Url,
+pub(crate) async fn post_request(
+    transport: &dyn Transport,
     command_path: &str,
     body: &str,
-) -> Result<String, Box<dyn Error>> {
-    let body = String::from(body);
-    let request_url = url.join(command_path).unwrap();
-    let request = client
-        .post(request_url)
-        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-        .body(body)
-        .build()
-        .unwrap();
-    let res = client.execute(request).await?;
+    repetition: Repetition,
+) -> Result<String, FritzerError> {
+    let description = format!("POST {}", command_path);
+    let response = retry(
+        &transport.retry_policy(),
+        &description,
+        repetition,
+        false,
+        || transport.post_form(command_path, body),
+    )
+    .await?;
 
-    Ok(res.text().await?)
+    into_body(response)
 }
 
 pub trait Command {
@@ -64,22 +86,19 @@ pub trait Command {
 pub trait Login: Command {
     async fn get_session_info(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
-    ) -> Result<Option<SessionInfo>, Box<dyn Error>>;
+        transport: &dyn Transport,
+    ) -> Result<Option<SessionInfo>, FritzerError>;
     async fn connect_with_sid(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         sid: &str,
-    ) -> Result<Option<SessionInfo>, Box<dyn Error>>;
+    ) -> Result<Option<SessionInfo>, FritzerError>;
     async fn connect_with_credentials(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         username: &str,
         password: &str,
-    ) -> Result<Option<SessionInfo>, Box<dyn Error>>;
+    ) -> Result<Option<SessionInfo>, FritzerError>;
 }
 
 pub struct FritzboxLogin;
@@ -91,112 +110,491 @@ impl Command for FritzboxLogin {
 impl Login for FritzboxLogin {
     async fn get_session_info(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
-    ) -> Result<Option<SessionInfo>, Box<dyn Error>> {
-        let response = get_request_with_command_path(client, url, Self::COMMAND_PATH).await?;
+        transport: &dyn Transport,
+    ) -> Result<Option<SessionInfo>, FritzerError> {
+        let response = get_request(
+            transport,
+            Self::COMMAND_PATH,
+            None,
+            Repetition::Idempotent,
+            false,
+        )
+        .await?;
 
-        Ok(Some(from_str::<SessionInfo>(&response).unwrap()))
+        parse_session_info(&response)
     }
 
     async fn connect_with_sid(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         sid: &str,
-    ) -> Result<Option<SessionInfo>, Box<dyn Error>> {
-        let body = format!("sid={}", sid);
-        let response = post_request(client, url, Self::COMMAND_PATH, &body).await?;
+    ) -> Result<Option<SessionInfo>, FritzerError> {
+        let body = form(&[("sid", sid)]);
+        let response =
+            post_request(transport, Self::COMMAND_PATH, &body, Repetition::Idempotent).await?;
 
-        Ok(Some(from_str::<SessionInfo>(&response).unwrap()))
+        parse_session_info(&response)
     }
 
     async fn connect_with_credentials(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         username: &str,
         response: &str,
-    ) -> Result<Option<SessionInfo>, Box<dyn Error>> {
-        let body = format!("username={}&response={}", username, response);
-        let response = post_request(client, url, Self::COMMAND_PATH, &body).await?;
+    ) -> Result<Option<SessionInfo>, FritzerError> {
+        let body = form(&[("username", username), ("response", response)]);
+        // A failed login attempt prolongs the block time, so never repeat it.
+        let response =
+            post_request(transport, Self::COMMAND_PATH, &body, Repetition::Unsent).await?;
 
-        Ok(Some(from_str::<SessionInfo>(&response).unwrap()))
+        parse_session_info(&response)
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Device {
-    pub ain: String,
+    pub ain: Ain,
     pub name: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchAction {
+    On,
+    Off,
+    Toggle,
+}
+
 #[async_trait]
-pub trait SwitchOperator: Command {
+pub trait SwitchOperator: Send + Sync {
     async fn get_switch(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<Device, FritzerError>;
+
+    async fn get_switch_list(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<Ain>, FritzerError>;
+
+    /// Returns whether the switch is on afterwards.
+    async fn set_switch(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        action: SwitchAction,
+    ) -> Result<bool, FritzerError>;
+
+    /// Whether the switch is on.
+    async fn get_switch_state(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        _ain: &Ain,
+    ) -> Result<bool, FritzerError> {
+        Err(FritzerError::Unsupported("getswitchstate".to_string()))
+    }
+
+    /// All devices and groups with the state of their functions.
+    async fn get_device_infos(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+    ) -> Result<Vec<DeviceInfo>, FritzerError> {
+        Err(FritzerError::Unsupported("getdevicelistinfos".to_string()))
+    }
+
+    async fn set_thermostat(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        _ain: &Ain,
+        _setpoint: Setpoint,
+    ) -> Result<(), FritzerError> {
+        Err(FritzerError::Unsupported("sethkrtsoll".to_string()))
+    }
+
+    /// Sets the brightness of a dimmable light in percent.
+    async fn set_level(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        _ain: &Ain,
+        _percentage: u8,
+    ) -> Result<(), FritzerError> {
+        Err(FritzerError::Unsupported("setlevelpercentage".to_string()))
+    }
+
+    async fn get_device_stats(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        _ain: &Ain,
+    ) -> Result<DeviceStats, FritzerError> {
+        Err(FritzerError::Unsupported("getbasicdevicestats".to_string()))
+    }
+
+    async fn get_templates(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+    ) -> Result<Vec<Template>, FritzerError> {
+        Err(FritzerError::Unsupported(
+            "gettemplatelistinfos".to_string(),
+        ))
+    }
+
+    async fn apply_template(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        _ain: &Ain,
+    ) -> Result<(), FritzerError> {
+        Err(FritzerError::Unsupported("applytemplate".to_string()))
+    }
+
+    async fn set_name(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        _ain: &Ain,
+        _name: &str,
+    ) -> Result<(), FritzerError> {
+        Err(FritzerError::Unsupported("setname".to_string()))
+    }
+
+    /// Sends any `switchcmd` and returns the body of the response. `command`
+    /// has been checked with [`RawCommand::validate`].
+    async fn raw_command(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        command: &RawCommand<'_>,
+    ) -> Result<String, FritzerError> {
+        Err(FritzerError::Unsupported(command.switchcmd.to_string()))
+    }
+}
+
+/// Allows choosing the operator at runtime, e.g. `Box<dyn SwitchOperator>`.
+#[async_trait]
+impl<S: SwitchOperator + ?Sized> SwitchOperator for Box<S> {
+    async fn get_switch(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<Device, FritzerError> {
+        (**self).get_switch(transport, sid, ain).await
+    }
+
+    async fn get_switch_list(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<Ain>, FritzerError> {
+        (**self).get_switch_list(transport, sid).await
+    }
+
+    async fn set_switch(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        action: SwitchAction,
+    ) -> Result<bool, FritzerError> {
+        (**self).set_switch(transport, sid, ain, action).await
+    }
+
+    async fn get_switch_state(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<bool, FritzerError> {
+        (**self).get_switch_state(transport, sid, ain).await
+    }
+
+    async fn get_device_infos(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<DeviceInfo>, FritzerError> {
+        (**self).get_device_infos(transport, sid).await
+    }
+
+    async fn set_thermostat(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        setpoint: Setpoint,
+    ) -> Result<(), FritzerError> {
+        (**self).set_thermostat(transport, sid, ain, setpoint).await
+    }
+
+    async fn set_level(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        percentage: u8,
+    ) -> Result<(), FritzerError> {
+        (**self).set_level(transport, sid, ain, percentage).await
+    }
+
+    async fn get_device_stats(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<DeviceStats, FritzerError> {
+        (**self).get_device_stats(transport, sid, ain).await
+    }
+
+    async fn get_templates(
+        &self,
+        transport: &dyn Transport,
         sid: &str,
-        ain: &str,
-    ) -> Result<Device, Box<dyn Error>>;
+    ) -> Result<Vec<Template>, FritzerError> {
+        (**self).get_templates(transport, sid).await
+    }
 
-    async fn get_switches(
+    async fn apply_template(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         sid: &str,
-    ) -> Result<Vec<Device>, Box<dyn Error>>;
+        ain: &Ain,
+    ) -> Result<(), FritzerError> {
+        (**self).apply_template(transport, sid, ain).await
+    }
+
+    async fn set_name(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        name: &str,
+    ) -> Result<(), FritzerError> {
+        (**self).set_name(transport, sid, ain, name).await
+    }
+
+    async fn raw_command(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        command: &RawCommand<'_>,
+    ) -> Result<String, FritzerError> {
+        (**self).raw_command(transport, sid, command).await
+    }
 }
 
 pub struct FritzboxSwitchOperator;
 impl Command for FritzboxSwitchOperator {
-    const COMMAND_PATH: &'static str = "/webservices/homeautoswitch.lua";
+    const COMMAND_PATH: &'static str = HOMEAUTOSWITCH_PATH;
 }
 
 #[async_trait]
 impl SwitchOperator for FritzboxSwitchOperator {
     async fn get_switch(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         sid: &str,
-        ain: &str,
-    ) -> Result<Device, Box<dyn Error>> {
-        let query = format!("switchcmd=getswitchname&sid={}&ain={}", sid, ain);
-        let body = get_request_with_query(client, url, Self::COMMAND_PATH, &query).await?;
-        let name = body
-            .strip_suffix("\n")
-            .expect("Cannot strip trailing newline.");
+        ain: &Ain,
+    ) -> Result<Device, FritzerError> {
+        let name = aha::execute(transport, sid, &GetSwitchName { ain }).await?;
 
         Ok(Device {
-            ain: ain.to_string(),
-            name: name.to_string(),
+            ain: ain.clone(),
+            name,
         })
     }
 
-    async fn get_switches(
+    async fn get_switch_list(
         &self,
-        client: &reqwest::Client,
-        url: &Url,
+        transport: &dyn Transport,
         sid: &str,
-    ) -> Result<Vec<Device>, Box<dyn Error>> {
-        let query = format!("switchcmd=getswitchlist&sid={}", sid);
+    ) -> Result<Vec<Ain>, FritzerError> {
+        aha::execute(transport, sid, &GetSwitchList).await
+    }
 
-        let body = get_request_with_query(client, url, Self::COMMAND_PATH, &query).await?;
-        let text = body
-            .strip_suffix("\n")
-            .expect("Cannot strip trailing newline.");
+    async fn set_switch(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        action: SwitchAction,
+    ) -> Result<bool, FritzerError> {
+        match action {
+            SwitchAction::On => aha::execute(transport, sid, &SetSwitchOn { ain }).await,
+            SwitchAction::Off => aha::execute(transport, sid, &SetSwitchOff { ain }).await,
+            SwitchAction::Toggle => aha::execute(transport, sid, &SetSwitchToggle { ain }).await,
+        }
+    }
 
-        let ains: Vec<&str> = text.split(",").collect();
-        let mut switches = Vec::new();
+    async fn get_switch_state(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<bool, FritzerError> {
+        aha::execute(transport, sid, &GetSwitchState { ain }).await
+    }
 
-        for (_, ain) in ains.iter().enumerate() {
-            let device = self.get_switch(client, url, sid, &ain).await?;
+    async fn get_device_infos(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<DeviceInfo>, FritzerError> {
+        aha::execute(transport, sid, &GetDeviceListInfos).await
+    }
 
-            switches.push(device);
-        }
+    async fn set_thermostat(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        setpoint: Setpoint,
+    ) -> Result<(), FritzerError> {
+        let tsoll = setpoint.to_api()?;
+
+        aha::execute(transport, sid, &SetHkrTsoll { ain, tsoll }).await
+    }
+
+    async fn set_level(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        percentage: u8,
+    ) -> Result<(), FritzerError> {
+        aha::execute(transport, sid, &SetLevelPercentage { ain, percentage }).await
+    }
+
+    async fn get_device_stats(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<DeviceStats, FritzerError> {
+        aha::execute(transport, sid, &GetBasicDeviceStats { ain }).await
+    }
+
+    async fn get_templates(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<Template>, FritzerError> {
+        aha::execute(transport, sid, &GetTemplateListInfos).await
+    }
+
+    async fn apply_template(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<(), FritzerError> {
+        aha::execute(transport, sid, &ApplyTemplate { ain }).await
+    }
+
+    async fn set_name(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        name: &str,
+    ) -> Result<(), FritzerError> {
+        aha::execute(transport, sid, &SetName { ain, name }).await
+    }
+
+    async fn raw_command(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        command: &RawCommand<'_>,
+    ) -> Result<String, FritzerError> {
+        aha::execute_raw(transport, sid, command).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transport::tests::FakeTransport;
+
+    #[tokio::test]
+    async fn fritzbox_switch_operator_get_switch_list_should_return_ains() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "087610000434,087610000435\n")]);
+
+        // Act
+        let result = FritzboxSwitchOperator
+            .get_switch_list(&transport, "0123456789abcdef")
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(2, result.len());
+        assert_eq!("087610000435", result[1].as_str());
+    }
+
+    #[tokio::test]
+    async fn fritzbox_switch_operator_get_switch_should_fail_on_forbidden() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(403, "")]);
+
+        // Act
+        let result = FritzboxSwitchOperator
+            .get_switch(
+                &transport,
+                "0123456789abcdef",
+                &"087610000434".parse().unwrap(),
+            )
+            .await;
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::NotConnected)));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_login_connect_with_credentials_should_encode_form() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(
+            200,
+            "<SessionInfo><SID>0123456789abcdef</SID><Challenge></Challenge>\
+             <BlockTime>0</BlockTime><Users></Users></SessionInfo>",
+        )]);
+
+        // Act
+        let _ = FritzboxLogin
+            .connect_with_credentials(&transport, "Jörg & Co", "d19cee81$506cf201")
+            .await;
+
+        // Assert
+        assert_eq!(
+            "POST /login_sid.lua?version=2 username=J%C3%B6rg+%26+Co&response=d19cee81%24506cf201",
+            transport.requests.lock().unwrap()[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn fritzbox_login_get_session_info_should_parse_session_info() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(
+            200,
+            "<SessionInfo><SID>0000000000000000</SID><Challenge>2$10$ab$10$cd</Challenge>\
+             <BlockTime>0</BlockTime><Users><User last=\"1\">fritzer</User></Users></SessionInfo>",
+        )]);
+
+        // Act
+        let result = FritzboxLogin.get_session_info(&transport).await.unwrap();
 
-        Ok(switches)
+        // Assert
+        let session_info = result.unwrap();
+        assert_eq!("2$10$ab$10$cd", session_info.challenge);
+        assert_eq!("fritzer", session_info.users.users[0].username);
     }
 }
@@ -0,0 +1,188 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+use crate::credentials::CredentialSource;
+use crate::error::FritzerError;
+use crate::output::OutputFormat;
+use crate::target::Tags;
+use crate::tls::Fingerprint;
+
+/// Contents of `config.toml`, e.g.
+///
+/// ```toml
+/// default_profile = "home"
+///
+/// [profiles.home]
+/// url = "http://fritz.box"
+/// username = "fritzer"
+/// password_command = "pass show fritzbox/home"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub url: Option<Url>,
+    /// Request timeout in seconds
+    pub timeout: Option<u64>,
+    pub proxy: Option<Url>,
+    /// Number of retries of requests failing while the FRITZ!Box is busy
+    pub retries: Option<u32>,
+    /// Maximum number of parallel requests of bulk operations
+    pub concurrency: Option<usize>,
+    /// PEM file of a CA certificate to trust for HTTPS
+    pub ca_file: Option<PathBuf>,
+    /// SHA-256 fingerprint of the only HTTPS certificate to accept
+    pub fingerprint: Option<Fingerprint>,
+    /// Do not verify the HTTPS certificate
+    #[serde(default)]
+    pub insecure: bool,
+    pub username: Option<String>,
+    pub password_file: Option<PathBuf>,
+    pub password_command: Option<String>,
+    /// Name of an environment variable containing the password
+    pub password_env: Option<String>,
+    pub sid_cache: Option<PathBuf>,
+    #[serde(default)]
+    pub no_sid_cache: bool,
+    /// Cache the PBKDF2-derived key next to the SID
+    #[serde(default)]
+    pub cache_derived_key: bool,
+    /// Output format of all commands unless given by `--output`
+    pub output: Option<OutputFormat>,
+    /// Targets addressable as `tag:<NAME>`
+    #[serde(default)]
+    pub tags: Tags,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/fritzer/config.toml`, falling back to
+    /// `~/.config/fritzer/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(config_home.join("fritzer").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, FritzerError> {
+        let content = fs::read_to_string(path)?;
+
+        Config::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Config, FritzerError> {
+        toml::from_str::<Config>(content).map_err(|e| FritzerError::Config(e.to_string()))
+    }
+
+    /// Returns the profile with the given name or the default profile. Without
+    /// a name and default profile, an empty profile is returned.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, FritzerError> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| FritzerError::Config(format!("Unknown profile '{}'.", name))),
+            None => Ok(Profile::default()),
+        }
+    }
+}
+
+impl Profile {
+    pub fn credential_source(&self) -> Option<CredentialSource> {
+        if let Some(path) = &self.password_file {
+            Some(CredentialSource::File(path.clone()))
+        } else if let Some(command) = &self.password_command {
+            Some(CredentialSource::Command(command.clone()))
+        } else {
+            self.password_env
+                .as_ref()
+                .map(|name| CredentialSource::Environment(name.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_profile_should_return_named_profile() {
+        // Arrange
+        let config = Config::parse(
+            r#"
+            default_profile = "home"
+
+            [profiles.home]
+            url = "http://fritz.box"
+
+            [profiles.office]
+            url = "https://office.example"
+            username = "admin"
+            password_command = "pass show fritzbox"
+            output = "ndjson"
+            "#,
+        )
+        .unwrap();
+
+        // Act
+        let profile = config.profile(Some("office")).unwrap();
+
+        // Assert
+        assert_eq!(
+            "https://office.example/",
+            profile.url.as_ref().unwrap().as_str()
+        );
+        assert_eq!(Some("admin".to_string()), profile.username);
+        assert_eq!(
+            Some(CredentialSource::Command("pass show fritzbox".to_string())),
+            profile.credential_source()
+        );
+        assert_eq!(Some(OutputFormat::Ndjson), profile.output);
+    }
+
+    #[test]
+    fn config_profile_should_fall_back_to_default_profile() {
+        // Arrange
+        let config = Config::parse(
+            r#"
+            default_profile = "home"
+
+            [profiles.home]
+            url = "http://fritz.box"
+            "#,
+        )
+        .unwrap();
+
+        // Act
+        let profile = config.profile(None).unwrap();
+
+        // Assert
+        assert_eq!("http://fritz.box/", profile.url.unwrap().as_str());
+    }
+
+    #[test]
+    fn config_profile_should_fail_on_unknown_profile() {
+        // Arrange
+        let config = Config::default();
+
+        // Act
+        let result = config.profile(Some("home"));
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::Config(_))));
+    }
+}
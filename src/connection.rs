@@ -1,26 +1,71 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Deserialize)]
+/// Serializes without the SID and the challenge, which must not end up in the
+/// output of commands.
+#[derive(Deserialize, Serialize, Clone)]
 pub struct SessionInfo {
-    #[serde(rename = "SID")]
+    #[serde(rename(deserialize = "SID"), skip_serializing)]
     pub sid: String,
-    #[serde(rename = "Challenge")]
+    #[serde(rename(deserialize = "Challenge"), skip_serializing)]
     pub challenge: String,
-    // #[serde(rename = "BlockTime")]
-    // block_time: u32
-    #[serde(rename = "Users")]
+    #[serde(rename(deserialize = "BlockTime"), default)]
+    pub block_time: u32,
+    #[serde(rename(deserialize = "Users"))]
     pub users: Users,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+/// Omits the SID, which grants access to the FRITZ!Box.
+impl fmt::Debug for SessionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionInfo")
+            .field("sid", &"<redacted>")
+            .field("challenge", &self.challenge)
+            .field("block_time", &self.block_time)
+            .field("users", &self.users)
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct Users {
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"))]
     pub users: Vec<User>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct User {
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"))]
     pub username: String,
     pub last: Option<i8>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_info_should_serialize_without_sid_and_challenge() {
+        // Arrange
+        let session_info = SessionInfo {
+            sid: "0123456789abcdef".to_string(),
+            challenge: "2$10$ab$10$cd".to_string(),
+            block_time: 0,
+            users: Users {
+                users: vec![User {
+                    username: "fritzer".to_string(),
+                    last: Some(1),
+                }],
+            },
+        };
+
+        // Act
+        let json = serde_json::to_string(&session_info).unwrap();
+
+        // Assert
+        assert_eq!(
+            r#"{"block_time":0,"users":{"users":[{"username":"fritzer","last":1}]}}"#,
+            json
+        );
+    }
+}
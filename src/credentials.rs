@@ -0,0 +1,151 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::error::FritzerError;
+
+pub const PASSWORD_ENV_VAR: &str = "FRITZER_PASSWORD";
+
+/// Where to obtain the FRITZ!Box password from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CredentialSource {
+    /// Ask interactively on the terminal.
+    Prompt,
+    /// Read the first line of the given file.
+    File(PathBuf),
+    /// Read the given environment variable.
+    Environment(String),
+    /// Run the given shell command and use the first line of its output.
+    Command(String),
+    /// Read the first line from standard input.
+    Stdin,
+}
+
+impl CredentialSource {
+    pub fn read_password(&self) -> Result<Zeroizing<String>, FritzerError> {
+        let password = match self {
+            CredentialSource::Prompt => {
+                Zeroizing::new(rpassword::prompt_password("Your password: ")?)
+            }
+            CredentialSource::File(path) => {
+                let mut content = Zeroizing::new(String::new());
+
+                File::open(path)?.read_to_string(&mut content)?;
+
+                first_line(&content)
+            }
+            CredentialSource::Environment(name) => match env::var(name) {
+                Ok(value) => Zeroizing::new(value),
+                Err(_) => {
+                    return Err(FritzerError::Credentials(format!(
+                        "Environment variable {} is not set.",
+                        name
+                    )))
+                }
+            },
+            CredentialSource::Command(command) => {
+                let mut output = shell(command)
+                    .stdin(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .output()?;
+
+                if !output.status.success() {
+                    output.stdout.zeroize();
+
+                    return Err(FritzerError::Credentials(format!(
+                        "Password command failed with {}.",
+                        output.status
+                    )));
+                }
+
+                let content = Zeroizing::new(String::from_utf8(output.stdout).map_err(|_| {
+                    FritzerError::Credentials("Password command printed invalid UTF-8.".to_string())
+                })?);
+
+                first_line(&content)
+            }
+            CredentialSource::Stdin => {
+                let mut content = Zeroizing::new(String::new());
+
+                io::stdin().lock().read_line(&mut content)?;
+
+                first_line(&content)
+            }
+        };
+
+        if password.is_empty() {
+            return Err(FritzerError::Credentials(
+                "The password must not be empty.".to_string(),
+            ));
+        }
+
+        Ok(password)
+    }
+}
+
+fn first_line(content: &str) -> Zeroizing<String> {
+    Zeroizing::new(content.lines().next().unwrap_or_default().to_string())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credential_source_environment_should_return_variable() {
+        // Arrange
+        env::set_var("FRITZER_TEST_PASSWORD", "secret");
+        let source = CredentialSource::Environment("FRITZER_TEST_PASSWORD".to_string());
+
+        // Act
+        let password = source.read_password().unwrap();
+
+        // Assert
+        assert_eq!("secret", password.as_str());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn credential_source_command_should_return_first_line() {
+        // Arrange
+        let source = CredentialSource::Command("printf 'secret\\nurl: fritz.box\\n'".to_string());
+
+        // Act
+        let password = source.read_password().unwrap();
+
+        // Assert
+        assert_eq!("secret", password.as_str());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn credential_source_command_should_fail_on_error_status() {
+        // Arrange
+        let source = CredentialSource::Command("exit 1".to_string());
+
+        // Act
+        let result = source.read_password();
+
+        // Assert
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,432 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ain::Ain;
+use crate::command::Device;
+use crate::error::FritzerError;
+
+/// A device or group as reported by `getdevicelistinfos`. Functions the
+/// device does not have are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    pub ain: Ain,
+    pub name: String,
+    pub product_name: String,
+    pub is_group: bool,
+    pub present: bool,
+    pub switch: Option<Switch>,
+    pub power_meter: Option<PowerMeter>,
+    /// Degrees Celsius, including the configured offset.
+    pub temperature: Option<f64>,
+    pub thermostat: Option<Thermostat>,
+    /// Brightness of a dimmable light in percent.
+    pub level: Option<u8>,
+    /// Whether an alarm sensor currently reports an alarm.
+    pub alarm: Option<bool>,
+    pub buttons: Vec<Button>,
+}
+
+impl From<&DeviceInfo> for Device {
+    fn from(device: &DeviceInfo) -> Self {
+        Device {
+            ain: device.ain.clone(),
+            name: device.name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Switch {
+    /// `None` while the FRITZ!Box does not know the state.
+    pub on: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PowerMeter {
+    /// Watts.
+    pub power: f64,
+    /// Watt hours since the device was set up.
+    pub energy: u64,
+    /// Volts.
+    pub voltage: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Thermostat {
+    /// Degrees Celsius measured by the thermostat.
+    pub current: Option<f64>,
+    pub target: Setpoint,
+}
+
+/// The target of a thermostat.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Setpoint {
+    Off,
+    On,
+    Celsius(f64),
+    Unknown,
+}
+
+impl Setpoint {
+    /// Decodes `tsoll`, which is given in steps of 0.5 °C from 8 °C to 28 °C,
+    /// or 253 for off and 254 for on.
+    pub fn from_api(value: u16) -> Setpoint {
+        match value {
+            253 => Setpoint::Off,
+            254 => Setpoint::On,
+            16..=56 => Setpoint::Celsius(f64::from(value) / 2.0),
+            _ => Setpoint::Unknown,
+        }
+    }
+
+    /// Encodes the setpoint as `tsoll`. Temperatures are rounded to 0.5 °C.
+    pub fn to_api(self) -> Result<u16, FritzerError> {
+        match self {
+            Setpoint::Off => Ok(253),
+            Setpoint::On => Ok(254),
+            Setpoint::Celsius(celsius) if (8.0..=28.0).contains(&celsius) => {
+                Ok((celsius * 2.0).round() as u16)
+            }
+            Setpoint::Celsius(celsius) => Err(FritzerError::InvalidValue(format!(
+                "Setpoint {} °C is outside of 8 °C to 28 °C.",
+                celsius
+            ))),
+            Setpoint::Unknown => Err(FritzerError::InvalidValue("Unknown setpoint.".to_string())),
+        }
+    }
+}
+
+/// Parses `off`, `on` or degrees Celsius such as `17`, `20.5` or `21°C`.
+impl FromStr for Setpoint {
+    type Err = FritzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let setpoint = match s.trim().to_lowercase().as_str() {
+            "off" => Setpoint::Off,
+            "on" => Setpoint::On,
+            value => value
+                .trim_end_matches("c")
+                .trim_end_matches('°')
+                .trim()
+                .parse()
+                .map(Setpoint::Celsius)
+                .map_err(|_| {
+                    FritzerError::InvalidValue(format!(
+                        "'{}' is neither off, on nor a temperature.",
+                        s
+                    ))
+                })?,
+        };
+
+        setpoint.to_api()?;
+
+        Ok(setpoint)
+    }
+}
+
+impl fmt::Display for Setpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Setpoint::Off => write!(f, "off"),
+            Setpoint::On => write!(f, "on"),
+            Setpoint::Celsius(celsius) => write!(f, "{:.1} °C", celsius),
+            Setpoint::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Button {
+    pub ain: Ain,
+    pub name: String,
+    /// Unix timestamp of the last press.
+    pub last_pressed: Option<u64>,
+}
+
+/// The FRITZ!Box lists all devices before all groups.
+#[derive(Deserialize)]
+struct RawDeviceList {
+    #[serde(default)]
+    device: Vec<RawDevice>,
+    #[serde(default)]
+    group: Vec<RawDevice>,
+}
+
+#[derive(Deserialize)]
+struct RawDevice {
+    identifier: String,
+    #[serde(default)]
+    productname: String,
+    #[serde(default)]
+    present: String,
+    #[serde(default)]
+    name: String,
+    switch: Option<RawSwitch>,
+    powermeter: Option<RawPowerMeter>,
+    temperature: Option<RawTemperature>,
+    hkr: Option<RawHkr>,
+    alert: Option<RawAlert>,
+    levelcontrol: Option<RawLevelControl>,
+    #[serde(default)]
+    button: Vec<RawButton>,
+}
+
+#[derive(Deserialize)]
+struct RawSwitch {
+    #[serde(default)]
+    state: String,
+}
+
+#[derive(Deserialize)]
+struct RawPowerMeter {
+    #[serde(default)]
+    power: String,
+    #[serde(default)]
+    energy: String,
+    #[serde(default)]
+    voltage: String,
+}
+
+#[derive(Deserialize)]
+struct RawTemperature {
+    #[serde(default)]
+    celsius: String,
+}
+
+#[derive(Deserialize)]
+struct RawHkr {
+    #[serde(default)]
+    tist: String,
+    #[serde(default)]
+    tsoll: String,
+}
+
+#[derive(Deserialize)]
+struct RawLevelControl {
+    #[serde(default)]
+    levelpercentage: String,
+}
+
+#[derive(Deserialize)]
+struct RawAlert {
+    #[serde(default)]
+    state: String,
+}
+
+#[derive(Deserialize)]
+struct RawButton {
+    identifier: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    lastpressedtimestamp: String,
+}
+
+/// Parses the response of `getdevicelistinfos`.
+pub fn parse_device_list(xml: &str) -> Result<Vec<DeviceInfo>, FritzerError> {
+    let list: RawDeviceList = serde_xml_rs::from_str(xml)?;
+
+    let devices = list.device.into_iter().map(|device| (device, false));
+    let groups = list.group.into_iter().map(|group| (group, true));
+
+    devices
+        .chain(groups)
+        .map(|(raw, is_group)| device_info(raw, is_group))
+        .collect()
+}
+
+fn device_info(raw: RawDevice, is_group: bool) -> Result<DeviceInfo, FritzerError> {
+    let switch = match raw.switch {
+        Some(switch) => Some(Switch {
+            on: number::<u8>("state", &switch.state)?.map(|state| state == 1),
+        }),
+        None => None,
+    };
+    let power_meter = match raw.powermeter {
+        Some(meter) => Some(PowerMeter {
+            power: number::<u64>("power", &meter.power)?.unwrap_or_default() as f64 / 1000.0,
+            energy: number("energy", &meter.energy)?.unwrap_or_default(),
+            voltage: number::<u64>("voltage", &meter.voltage)?.map(|mv| mv as f64 / 1000.0),
+        }),
+        None => None,
+    };
+    let temperature = match raw.temperature {
+        Some(temperature) => {
+            number::<i32>("celsius", &temperature.celsius)?.map(|tenths| f64::from(tenths) / 10.0)
+        }
+        None => None,
+    };
+    let thermostat = match raw.hkr {
+        Some(hkr) => Some(Thermostat {
+            current: number::<u16>("tist", &hkr.tist)?.map(|value| f64::from(value) / 2.0),
+            target: number("tsoll", &hkr.tsoll)?
+                .map(Setpoint::from_api)
+                .unwrap_or(Setpoint::Unknown),
+        }),
+        None => None,
+    };
+    let level = match raw.levelcontrol {
+        Some(control) => number("levelpercentage", &control.levelpercentage)?,
+        None => None,
+    };
+    let alarm = match raw.alert {
+        Some(alert) => number::<u32>("state", &alert.state)?.map(|state| state != 0),
+        None => None,
+    };
+    let buttons = raw
+        .button
+        .into_iter()
+        .map(|button| {
+            Ok(Button {
+                ain: button.identifier.parse()?,
+                name: button.name,
+                last_pressed: number("lastpressedtimestamp", &button.lastpressedtimestamp)?,
+            })
+        })
+        .collect::<Result<_, FritzerError>>()?;
+
+    Ok(DeviceInfo {
+        ain: raw.identifier.parse()?,
+        name: raw.name,
+        product_name: raw.productname,
+        is_group,
+        present: raw.present == "1",
+        switch,
+        power_meter,
+        temperature,
+        thermostat,
+        level,
+        alarm,
+        buttons,
+    })
+}
+
+/// Parses a numeric element, which is empty while the value is unknown.
+fn number<T: FromStr>(element: &str, value: &str) -> Result<Option<T>, FritzerError> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value.parse().map(Some).map_err(|_| {
+            FritzerError::InvalidResponse(format!("Invalid <{}> '{}'.", element, value))
+        }),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const DEVICE_LIST: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<devicelist version="1" fwversion="7.57">
+<device identifier="08761 0000434" id="17" functionbitmask="35712" fwversion="04.16" manufacturer="AVM" productname="FRITZ!DECT 200">
+<present>1</present><txbusy>0</txbusy><name>Kettle</name>
+<switch><state>1</state><mode>manuell</mode><lock>0</lock><devicelock>0</devicelock></switch>
+<simpleonoff><state>1</state></simpleonoff>
+<powermeter><voltage>230051</voltage><power>1520000</power><energy>707</energy></powermeter>
+<temperature><celsius>225</celsius><offset>0</offset></temperature>
+</device>
+<device identifier="09995 0000123" id="18" functionbitmask="320" fwversion="05.02" manufacturer="AVM" productname="FRITZ!DECT 301">
+<present>1</present><txbusy>0</txbusy><name>Radiator</name><battery>80</battery><batterylow>0</batterylow>
+<temperature><celsius>195</celsius><offset>-5</offset></temperature>
+<hkr><tist>39</tist><tsoll>253</tsoll><absenk>32</absenk><komfort>42</komfort><lock>0</lock><devicelock>0</devicelock></hkr>
+</device>
+<device identifier="13096 0001234" id="19" functionbitmask="1048864" fwversion="05.10" manufacturer="AVM" productname="FRITZ!DECT 440">
+<present>0</present><txbusy>0</txbusy><name>Hallway</name>
+<button identifier="13096 0001234-1" id="5000"><name>Hallway: top right</name><lastpressedtimestamp>1700000000</lastpressedtimestamp></button>
+<button identifier="13096 0001234-3" id="5001"><name>Hallway: bottom right</name><lastpressedtimestamp></lastpressedtimestamp></button>
+</device>
+<group identifier="grp303E4F-3F7D9BE07" id="900" functionbitmask="6784" fwversion="1.0" manufacturer="AVM" productname="">
+<present>1</present><txbusy>0</txbusy><name>Living room</name>
+<levelcontrol><level>128</level><levelpercentage>50</levelpercentage></levelcontrol>
+<switch><state></state><mode>manuell</mode><lock></lock><devicelock></devicelock></switch>
+<groupinfo><masterdeviceid>0</masterdeviceid><members>17</members></groupinfo>
+</group>
+</devicelist>
+"#;
+
+    #[test]
+    fn parse_device_list_should_parse_devices_and_groups() {
+        // Arrange
+        let xml = DEVICE_LIST;
+
+        // Act
+        let devices = parse_device_list(xml).unwrap();
+
+        // Assert
+        assert_eq!(4, devices.len());
+        assert_eq!("087610000434", devices[0].ain.as_str());
+        assert_eq!(Some(Switch { on: Some(true) }), devices[0].switch);
+        assert_eq!(
+            Some(PowerMeter {
+                power: 1520.0,
+                energy: 707,
+                voltage: Some(230.051)
+            }),
+            devices[0].power_meter
+        );
+        assert_eq!(Some(22.5), devices[0].temperature);
+        assert_eq!(
+            Some(Thermostat {
+                current: Some(19.5),
+                target: Setpoint::Off
+            }),
+            devices[1].thermostat
+        );
+        assert!(!devices[2].present);
+        assert_eq!(Some(1700000000), devices[2].buttons[0].last_pressed);
+        assert_eq!(None, devices[2].buttons[1].last_pressed);
+        assert!(devices[3].is_group);
+        assert_eq!(Some(Switch { on: None }), devices[3].switch);
+        assert_eq!(Some(50), devices[3].level);
+    }
+
+    #[test]
+    fn setpoint_should_round_trip_api_values() {
+        // Arrange
+        let setpoints = [Setpoint::Off, Setpoint::On, Setpoint::Celsius(21.5)];
+
+        // Act
+        let values: Vec<u16> = setpoints.iter().map(|s| s.to_api().unwrap()).collect();
+
+        // Assert
+        assert_eq!(vec![253, 254, 43], values);
+        assert_eq!(
+            setpoints.to_vec(),
+            values
+                .into_iter()
+                .map(Setpoint::from_api)
+                .collect::<Vec<_>>()
+        );
+        assert!(Setpoint::Celsius(30.0).to_api().is_err());
+    }
+
+    #[test]
+    fn setpoint_should_parse_keywords_and_temperatures() {
+        // Arrange
+        let values = ["off", "ON", "17", "20.5 °C", "30", "warm"];
+
+        // Act
+        let setpoints: Vec<_> = values.iter().map(|v| v.parse::<Setpoint>()).collect();
+
+        // Assert
+        assert_eq!(Setpoint::Off, *setpoints[0].as_ref().unwrap());
+        assert_eq!(Setpoint::On, *setpoints[1].as_ref().unwrap());
+        assert_eq!(Setpoint::Celsius(17.0), *setpoints[2].as_ref().unwrap());
+        assert_eq!(Setpoint::Celsius(20.5), *setpoints[3].as_ref().unwrap());
+        assert!(matches!(setpoints[4], Err(FritzerError::InvalidValue(_))));
+        assert!(matches!(setpoints[5], Err(FritzerError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn parse_device_list_should_accept_empty_list() {
+        // Arrange
+        let xml = r#"<devicelist version="1"></devicelist>"#;
+
+        // Act
+        let devices = parse_device_list(xml).unwrap();
+
+        // Assert
+        assert!(devices.is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::fmt;
+
+use crate::aha::{
+    self, AhaCommand, ApplyTemplate, RawCommand, SetHkrTsoll, SetLevelPercentage, SetName,
+    SetSwitchOff, SetSwitchOn, SetSwitchToggle, HOMEAUTOSWITCH_PATH,
+};
+use crate::ain::Ain;
+use crate::command::{Device, FritzboxSwitchOperator, SwitchAction, SwitchOperator};
+use crate::device::{DeviceInfo, Setpoint};
+use crate::error::FritzerError;
+use crate::stats::DeviceStats;
+use crate::template::Template;
+use crate::transport::Transport;
+
+/// Replaces the SID in recorded requests.
+pub const REDACTED_SID: &str = "REDACTED";
+
+/// A request that was recorded instead of sent.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedRequest {
+    /// The device, group or template the request changes.
+    pub ain: Option<Ain>,
+    pub path: String,
+    /// The URL-encoded query with the SID replaced by [`REDACTED_SID`].
+    pub query: String,
+}
+
+impl RecordedRequest {
+    fn of<C: AhaCommand>(command: &C) -> RecordedRequest {
+        RecordedRequest {
+            ain: command.ain().cloned(),
+            path: C::COMMAND_PATH.to_string(),
+            query: aha::query(command, REDACTED_SID),
+        }
+    }
+}
+
+impl From<&RawCommand<'_>> for RecordedRequest {
+    fn from(command: &RawCommand<'_>) -> RecordedRequest {
+        RecordedRequest {
+            ain: command.ain.cloned(),
+            path: HOMEAUTOSWITCH_PATH.to_string(),
+            query: command.query(REDACTED_SID),
+        }
+    }
+}
+
+impl fmt::Display for RecordedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GET {}?{}", self.path, self.query)
+    }
+}
+
+/// Reads with `inner` but hands every write to a callback instead of sending
+/// it. Writes succeed as if the FRITZ!Box had accepted them.
+pub struct RecordingSwitchOperator<S = FritzboxSwitchOperator> {
+    inner: S,
+    on_request: Box<dyn Fn(RecordedRequest) + Send + Sync>,
+}
+
+impl<S: SwitchOperator> RecordingSwitchOperator<S> {
+    pub fn new(
+        inner: S,
+        on_request: impl Fn(RecordedRequest) + Send + Sync + 'static,
+    ) -> RecordingSwitchOperator<S> {
+        RecordingSwitchOperator {
+            inner,
+            on_request: Box::new(on_request),
+        }
+    }
+
+    fn record<C: AhaCommand>(&self, command: &C) {
+        (self.on_request)(RecordedRequest::of(command));
+    }
+}
+
+#[async_trait]
+impl<S: SwitchOperator> SwitchOperator for RecordingSwitchOperator<S> {
+    async fn get_switch(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<Device, FritzerError> {
+        self.inner.get_switch(transport, sid, ain).await
+    }
+
+    async fn get_switch_list(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<Ain>, FritzerError> {
+        self.inner.get_switch_list(transport, sid).await
+    }
+
+    /// Reads the current state to predict the result of a toggle.
+    async fn set_switch(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+        action: SwitchAction,
+    ) -> Result<bool, FritzerError> {
+        match action {
+            SwitchAction::On => {
+                self.record(&SetSwitchOn { ain });
+
+                Ok(true)
+            }
+            SwitchAction::Off => {
+                self.record(&SetSwitchOff { ain });
+
+                Ok(false)
+            }
+            SwitchAction::Toggle => {
+                let on = self.inner.get_switch_state(transport, sid, ain).await?;
+
+                self.record(&SetSwitchToggle { ain });
+
+                Ok(!on)
+            }
+        }
+    }
+
+    async fn get_switch_state(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<bool, FritzerError> {
+        self.inner.get_switch_state(transport, sid, ain).await
+    }
+
+    async fn get_device_infos(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<DeviceInfo>, FritzerError> {
+        self.inner.get_device_infos(transport, sid).await
+    }
+
+    async fn set_thermostat(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        ain: &Ain,
+        setpoint: Setpoint,
+    ) -> Result<(), FritzerError> {
+        let tsoll = setpoint.to_api()?;
+
+        self.record(&SetHkrTsoll { ain, tsoll });
+
+        Ok(())
+    }
+
+    async fn set_level(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        ain: &Ain,
+        percentage: u8,
+    ) -> Result<(), FritzerError> {
+        self.record(&SetLevelPercentage { ain, percentage });
+
+        Ok(())
+    }
+
+    async fn get_device_stats(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        ain: &Ain,
+    ) -> Result<DeviceStats, FritzerError> {
+        self.inner.get_device_stats(transport, sid, ain).await
+    }
+
+    async fn get_templates(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+    ) -> Result<Vec<Template>, FritzerError> {
+        self.inner.get_templates(transport, sid).await
+    }
+
+    async fn apply_template(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        ain: &Ain,
+    ) -> Result<(), FritzerError> {
+        self.record(&ApplyTemplate { ain });
+
+        Ok(())
+    }
+
+    async fn set_name(
+        &self,
+        _transport: &dyn Transport,
+        _sid: &str,
+        ain: &Ain,
+        name: &str,
+    ) -> Result<(), FritzerError> {
+        self.record(&SetName { ain, name });
+
+        Ok(())
+    }
+
+    /// Sends reading commands and records all others with an empty response.
+    async fn raw_command(
+        &self,
+        transport: &dyn Transport,
+        sid: &str,
+        command: &RawCommand<'_>,
+    ) -> Result<String, FritzerError> {
+        if command.is_read() {
+            return self.inner.raw_command(transport, sid, command).await;
+        }
+
+        (self.on_request)(RecordedRequest::from(command));
+
+        Ok(String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::transport::tests::FakeTransport;
+
+    fn operator() -> (RecordingSwitchOperator, Arc<Mutex<Vec<RecordedRequest>>>) {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let sink = recorded.clone();
+        let operator = RecordingSwitchOperator::new(FritzboxSwitchOperator, move |request| {
+            sink.lock().unwrap().push(request)
+        });
+
+        (operator, recorded)
+    }
+
+    #[tokio::test]
+    async fn set_switch_should_record_request_without_sid() {
+        // Arrange
+        let transport = FakeTransport::with_responses(Vec::new());
+        let (operator, recorded) = operator();
+        let ain: Ain = "087610000434".parse().unwrap();
+
+        // Act
+        let on = operator
+            .set_switch(&transport, "0123456789abcdef", &ain, SwitchAction::Off)
+            .await
+            .unwrap();
+
+        // Assert
+        assert!(!on);
+        assert!(transport.requests.lock().unwrap().is_empty());
+        assert_eq!(
+            "GET /webservices/homeautoswitch.lua?switchcmd=setswitchoff&sid=REDACTED&ain=087610000434",
+            recorded.lock().unwrap()[0].to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn set_switch_should_read_state_before_toggle() {
+        // Arrange
+        let transport = FakeTransport::with_responses(vec![(200, "1\n")]);
+        let (operator, recorded) = operator();
+        let ain: Ain = "087610000434".parse().unwrap();
+
+        // Act
+        let on = operator
+            .set_switch(&transport, "0123456789abcdef", &ain, SwitchAction::Toggle)
+            .await
+            .unwrap();
+
+        // Assert
+        assert!(!on);
+        assert!(transport.requests.lock().unwrap()[0].contains("switchcmd=getswitchstate"));
+        assert!(recorded.lock().unwrap()[0]
+            .query
+            .contains("switchcmd=setswitchtoggle"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum FritzerError {
+    /// The request to the FRITZ!Box failed.
+    Http(reqwest::Error),
+    /// The FRITZ!Box answered with an unexpected HTTP status.
+    Status(u16),
+    /// The FRITZ!Box sent XML that does not match the expected structure.
+    Xml(serde_xml_rs::Error),
+    Io(io::Error),
+    /// There is no valid session, or the FRITZ!Box rejected the SID.
+    NotConnected,
+    /// The FRITZ!Box rejected the credentials.
+    LoginFailed,
+    /// The FRITZ!Box blocks login attempts for the given number of seconds.
+    Blocked(u32),
+    /// The AIN is malformed or the FRITZ!Box does not accept it.
+    InvalidAin(String),
+    DeviceNotPresent(String),
+    /// No device matches the target.
+    UnknownTarget(String),
+    /// The target matches the given candidates, but only one device is allowed.
+    AmbiguousTarget(String, Vec<String>),
+    Unsupported(String),
+    /// A value passed to a command is out of range.
+    InvalidValue(String),
+    InvalidResponse(String),
+    /// The TLS configuration is invalid or the certificate could not be obtained.
+    Tls(String),
+    /// The password could not be obtained from its credential source.
+    Credentials(String),
+    /// A condition checked by a command does not hold, e.g. a switch is off.
+    CheckFailed(String),
+    /// The configuration file is invalid or lacks the selected profile.
+    Config(String),
+}
+
+impl fmt::Display for FritzerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FritzerError::Http(e) => write!(f, "HTTP request failed: {}", e),
+            FritzerError::Status(status) => {
+                write!(f, "The FRITZ!Box answered with HTTP {}.", status)
+            }
+            FritzerError::Xml(e) => write!(f, "Unable to parse XML response: {}", e),
+            FritzerError::Io(e) => write!(f, "{}", e),
+            FritzerError::NotConnected => write!(f, "Not connected to the FRITZ!Box."),
+            FritzerError::LoginFailed => write!(f, "Login to the FRITZ!Box failed."),
+            FritzerError::Blocked(seconds) => write!(
+                f,
+                "The FRITZ!Box blocks login attempts for {} seconds.",
+                seconds
+            ),
+            FritzerError::InvalidAin(ain) => write!(
+                f,
+                "Invalid AIN '{}'. Expected e.g. '08761 0000434', '11630 0123456-1' or 'grp303E4F-3F7D9BE07'.",
+                ain
+            ),
+            FritzerError::DeviceNotPresent(ain) => write!(f, "Device {} is not present.", ain),
+            FritzerError::UnknownTarget(target) => {
+                write!(f, "No device matches '{}'.", target)
+            }
+            FritzerError::AmbiguousTarget(target, candidates) => write!(
+                f,
+                "'{}' matches several devices: {}. Use the full name, a glob pattern or the AIN.",
+                target,
+                candidates.join(", ")
+            ),
+            FritzerError::Unsupported(what) => write!(f, "Unsupported: {}", what),
+            FritzerError::InvalidValue(reason) => write!(f, "{}", reason),
+            FritzerError::InvalidResponse(reason) => {
+                write!(f, "Invalid response from the FRITZ!Box: {}", reason)
+            }
+            FritzerError::Tls(reason) => write!(f, "{}", reason),
+            FritzerError::Credentials(reason) => write!(f, "{}", reason),
+            FritzerError::CheckFailed(reason) => write!(f, "{}", reason),
+            FritzerError::Config(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Error for FritzerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FritzerError::Http(e) => Some(e),
+            FritzerError::Xml(e) => Some(e),
+            FritzerError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for FritzerError {
+    fn from(e: reqwest::Error) -> Self {
+        FritzerError::Http(e)
+    }
+}
+
+impl From<serde_xml_rs::Error> for FritzerError {
+    fn from(e: serde_xml_rs::Error) -> Self {
+        FritzerError::Xml(e)
+    }
+}
+
+impl From<io::Error> for FritzerError {
+    fn from(e: io::Error) -> Self {
+        FritzerError::Io(e)
+    }
+}
@@ -1,39 +1,62 @@
-use ring::{digest, pbkdf2};
-use std::num::NonZeroU32;
+use std::sync::Arc;
 use url::Url;
 
+pub mod aha;
+pub mod ain;
+pub mod builder;
+pub mod bulk;
 pub mod command;
+pub mod config;
 pub mod connection;
+pub mod credentials;
+pub mod device;
+pub mod dry_run;
+pub mod error;
+pub mod output;
+pub mod retry;
+pub mod session;
+pub mod sid_cache;
+pub mod stats;
+pub mod target;
+pub mod template;
+pub mod tls;
+pub mod transport;
+pub mod watch;
 
-use crate::command::{Device, FritzboxLogin, FritzboxSwitchOperator, Login, SwitchOperator};
+use crate::aha::RawCommand;
+use crate::ain::Ain;
+use crate::builder::FritzboxBuilder;
+use crate::bulk::BulkResult;
+use crate::command::{
+    Device, FritzboxLogin, FritzboxSwitchOperator, Login, SwitchAction, SwitchOperator,
+};
 use crate::connection::SessionInfo;
+use crate::credentials::CredentialSource;
+use crate::device::{DeviceInfo, Setpoint};
+use crate::error::FritzerError;
+use crate::session::{DerivedKey, SessionManager};
+use crate::stats::DeviceStats;
+use crate::target::{Tags, Target};
+use crate::template::Template;
+use crate::transport::Transport;
 
-static INVALID_SESSION: &str = "0000000000000000";
-const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
-type Credential = [u8; CREDENTIAL_LEN];
-
+/// A FRITZ!Box without a session. Connecting yields a [`ConnectedFritzbox`],
+/// which is the only handle exposing device operations.
 pub struct Fritzbox<
     L: Login + ?Sized = FritzboxLogin,
     S: SwitchOperator + ?Sized = FritzboxSwitchOperator,
 > {
-    pub session_info: Option<SessionInfo>,
+    session_info: Option<SessionInfo>,
 
-    url: Url,
-    client: reqwest::Client,
-    login: Box<L>,
-    switch_operator: Box<S>,
+    transport: Arc<dyn Transport>,
+    login: Arc<L>,
+    switch_operator: Arc<S>,
+    concurrency: usize,
 }
 
 impl Fritzbox<FritzboxLogin, FritzboxSwitchOperator> {
-    pub fn new(url: Url) -> Fritzbox<FritzboxLogin, FritzboxSwitchOperator> {
-        Fritzbox {
-            session_info: None::<SessionInfo>,
-
-            url,
-            client: reqwest::Client::new(),
-            login: Box::new(FritzboxLogin),
-            switch_operator: Box::new(FritzboxSwitchOperator),
-        }
+    pub fn builder(url: Url) -> FritzboxBuilder {
+        FritzboxBuilder::new(url)
     }
 }
 
@@ -42,113 +65,331 @@ where
     L: Login,
     S: SwitchOperator,
 {
-    pub fn with_login(url: Url, login: L) -> Fritzbox<L, FritzboxSwitchOperator> {
-        Fritzbox {
-            session_info: None::<SessionInfo>,
-
-            url,
-            client: reqwest::Client::new(),
-            login: Box::new(login),
-            switch_operator: Box::new(FritzboxSwitchOperator {}),
-        }
+    pub fn session_info(&self) -> Option<&SessionInfo> {
+        self.session_info.as_ref()
+    }
+
+    pub async fn update_session_info(&mut self) -> Result<(), FritzerError> {
+        self.session_info = self.login.get_session_info(self.transport.as_ref()).await?;
+
+        Ok(())
+    }
+
+    /// Fails with [`FritzerError::NotConnected`] if the FRITZ!Box does not
+    /// accept the SID.
+    pub async fn connect_with_sid(
+        &self,
+        sid: &str,
+    ) -> Result<ConnectedFritzbox<L, S>, FritzerError> {
+        let session = self.session_manager();
+
+        session.connect_with_sid(sid).await?;
+
+        Ok(self.connected(session))
+    }
+
+    /// Fails with [`FritzerError::LoginFailed`] or [`FritzerError::Blocked`]
+    /// if the FRITZ!Box rejects the credentials.
+    pub async fn connect_with_credentials(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<ConnectedFritzbox<L, S>, FritzerError> {
+        let session = self.session_manager();
+
+        session.connect_with_credentials(username, password).await?;
+
+        Ok(self.connected(session))
+    }
+
+    /// Logs in with a key obtained from [`ConnectedFritzbox::derived_key`]
+    /// instead of the password.
+    pub async fn connect_with_derived_key(
+        &self,
+        username: &str,
+        key: DerivedKey,
+    ) -> Result<ConnectedFritzbox<L, S>, FritzerError> {
+        let session = self.session_manager();
+
+        session.connect_with_derived_key(username, key).await?;
+
+        Ok(self.connected(session))
     }
 
-    pub fn with_switchbox_operator(url: Url, login: L, switch_operator: S) -> Fritzbox<L, S> {
-        Fritzbox {
-            session_info: None::<SessionInfo>,
+    fn session_manager(&self) -> SessionManager<L> {
+        SessionManager::new(self.transport.clone(), self.login.clone())
+    }
 
-            url,
-            client: reqwest::Client::new(),
-            login: Box::new(login),
-            switch_operator: Box::new(switch_operator),
+    fn connected(&self, session: SessionManager<L>) -> ConnectedFritzbox<L, S> {
+        ConnectedFritzbox {
+            session,
+            switch_operator: self.switch_operator.clone(),
+            concurrency: self.concurrency,
         }
     }
+}
+
+/// A FRITZ!Box with an authenticated session.
+pub struct ConnectedFritzbox<
+    L: Login + ?Sized = FritzboxLogin,
+    S: SwitchOperator + ?Sized = FritzboxSwitchOperator,
+> {
+    session: SessionManager<L>,
+    switch_operator: Arc<S>,
+    concurrency: usize,
+}
+
+impl<L, S> ConnectedFritzbox<L, S>
+where
+    L: Login,
+    S: SwitchOperator,
+{
+    pub fn session_info(&self) -> Option<SessionInfo> {
+        self.session.session_info()
+    }
+
+    pub fn sid(&self) -> Option<String> {
+        self.session.sid()
+    }
 
     pub fn is_connected(&self) -> bool {
-        match &self.session_info {
-            Some(s) => !s.sid.eq(INVALID_SESSION),
-            None => false,
-        }
+        self.session.is_connected()
     }
 
-    pub async fn update_session_info(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.session_info = self.login.get_session_info(&self.client, &self.url).await?;
+    /// The first PBKDF2 stage of the password, if connected with credentials.
+    pub fn derived_key(&self) -> Option<DerivedKey> {
+        self.session.derived_key()
+    }
 
-        Ok(())
+    /// Logs in again with the password of `source` once the SID expires,
+    /// see [`SessionManager::set_credential_source`].
+    pub fn set_credential_source(&self, username: &str, source: CredentialSource) {
+        self.session.set_credential_source(username, source);
     }
 
-    pub async fn connect_with_sid(
-        &mut self,
-        sid: &str,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
-        self.session_info = self
-            .login
-            .connect_with_sid(&self.client, &self.url, sid)
-            .await?;
+    /// Calls `on_renewal` with the new SID after logging in again, see
+    /// [`SessionManager::on_renewal`].
+    pub fn on_session_renewal(&self, on_renewal: impl Fn(&str) + Send + Sync + 'static) {
+        self.session.on_renewal(on_renewal);
+    }
 
-        Ok(self.is_connected())
+    /// Calls `on_use` with the SID after every successful request, see
+    /// [`SessionManager::on_use`].
+    pub fn on_session_use(&self, on_use: impl Fn(&str) + Send + Sync + 'static) {
+        self.session.on_use(on_use);
     }
 
-    pub async fn connect_with_credentials(
-        &mut self,
-        username: &str,
-        password: &str,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
-        self.update_session_info().await?;
+    pub async fn get_switch(&self, ain: &Ain) -> Result<Device, FritzerError> {
+        let transport = self.session.transport();
 
-        let session_info = self.session_info.as_ref().unwrap();
-        let response = Self::get_challenge_response(&session_info.challenge, password);
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator.get_switch(transport, &sid, ain).await
+            })
+            .await
+    }
 
-        self.session_info = self
-            .login
-            .connect_with_credentials(&self.client, &self.url, username, &response)
-            .await?;
+    /// Fails with the first error if the name of any switch cannot be read.
+    pub async fn get_switches(&self) -> Result<Vec<Device>, FritzerError> {
+        let ains = self.get_switch_list().await?;
 
-        Ok(self.is_connected())
+        self.get_switches_by_ain(&ains)
+            .await
+            .into_iter()
+            .map(|r| r.result)
+            .collect()
     }
 
-    pub async fn get_switch(&self, ain: &str) -> Result<Device, Box<dyn std::error::Error>> {
-        let session_info = self.session_info.as_ref().unwrap();
+    /// Resolves `targets` to AINs, see [`target::resolve`]. The names of the
+    /// switches are only read if a target is not an AIN.
+    pub async fn resolve_targets(
+        &self,
+        targets: &[Target],
+        tags: &Tags,
+    ) -> Result<Vec<Ain>, FritzerError> {
+        let devices = match targets.iter().any(Target::needs_devices) {
+            true => self.get_switches().await?,
+            false => Vec::new(),
+        };
 
-        Ok(self
-            .switch_operator
-            .get_switch(&self.client, &self.url, &session_info.sid, ain)
-            .await?)
+        target::resolve(targets, &devices, tags)
     }
 
-    pub async fn get_switches(&self) -> Result<Vec<Device>, Box<dyn std::error::Error>> {
-        let session_info = self.session_info.as_ref().unwrap();
+    pub async fn get_switch_list(&self) -> Result<Vec<Ain>, FritzerError> {
+        let transport = self.session.transport();
 
-        Ok(self
-            .switch_operator
-            .get_switches(&self.client, &self.url, &session_info.sid)
-            .await?)
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator.get_switch_list(transport, &sid).await
+            })
+            .await
     }
 
-    fn get_challenge_response(challenge: &str, password: &str) -> String {
-        let challenges: Vec<&str> = challenge.split('$').collect();
-        let salt1 = hex::decode(challenges[2]).unwrap();
-        let salt2 = hex::decode(challenges[4]).unwrap();
+    pub async fn get_switch_state(&self, ain: &Ain) -> Result<bool, FritzerError> {
+        let transport = self.session.transport();
 
-        let mut hash1: Credential = [0u8; CREDENTIAL_LEN];
-        pbkdf2::derive(
-            pbkdf2::PBKDF2_HMAC_SHA256,
-            NonZeroU32::new(challenges[1].parse::<u32>().unwrap()).unwrap(),
-            &salt1,
-            password.as_bytes(),
-            &mut hash1,
-        );
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .get_switch_state(transport, &sid, ain)
+                    .await
+            })
+            .await
+    }
 
-        let mut hash2: Credential = [0u8; CREDENTIAL_LEN];
-        pbkdf2::derive(
-            pbkdf2::PBKDF2_HMAC_SHA256,
-            NonZeroU32::new(challenges[3].parse::<u32>().unwrap()).unwrap(),
-            &salt2,
-            &hash1,
-            &mut hash2,
-        );
+    pub async fn get_device_infos(&self) -> Result<Vec<DeviceInfo>, FritzerError> {
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator.get_device_infos(transport, &sid).await
+            })
+            .await
+    }
+
+    /// Fails with [`FritzerError::InvalidValue`] if the setpoint is out of
+    /// range.
+    pub async fn set_thermostat(&self, ain: &Ain, setpoint: Setpoint) -> Result<(), FritzerError> {
+        setpoint.to_api()?;
+
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .set_thermostat(transport, &sid, ain, setpoint)
+                    .await
+            })
+            .await
+    }
+
+    /// Fails with [`FritzerError::InvalidValue`] if `percentage` exceeds 100.
+    pub async fn set_level(&self, ain: &Ain, percentage: u8) -> Result<(), FritzerError> {
+        if percentage > 100 {
+            return Err(FritzerError::InvalidValue(format!(
+                "Level {}% exceeds 100%.",
+                percentage
+            )));
+        }
+
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .set_level(transport, &sid, ain, percentage)
+                    .await
+            })
+            .await
+    }
+
+    pub async fn get_device_stats(&self, ain: &Ain) -> Result<DeviceStats, FritzerError> {
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .get_device_stats(transport, &sid, ain)
+                    .await
+            })
+            .await
+    }
+
+    pub async fn set_thermostats(&self, ains: &[Ain], setpoint: Setpoint) -> Vec<BulkResult<()>> {
+        bulk::run(ains, self.concurrency, |ain| {
+            self.set_thermostat(ain, setpoint)
+        })
+        .await
+    }
+
+    pub async fn get_templates(&self) -> Result<Vec<Template>, FritzerError> {
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(
+                |sid| async move { self.switch_operator.get_templates(transport, &sid).await },
+            )
+            .await
+    }
+
+    pub async fn apply_template(&self, ain: &Ain) -> Result<(), FritzerError> {
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .apply_template(transport, &sid, ain)
+                    .await
+            })
+            .await
+    }
 
-        format!("{}%24{}", challenges[4], hex::encode(hash2))
+    /// Fails with [`FritzerError::InvalidValue`] if `name` is empty.
+    pub async fn set_name(&self, ain: &Ain, name: &str) -> Result<(), FritzerError> {
+        if name.trim().is_empty() {
+            return Err(FritzerError::InvalidValue(
+                "The name must not be empty.".to_string(),
+            ));
+        }
+
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .set_name(transport, &sid, ain, name)
+                    .await
+            })
+            .await
+    }
+
+    /// Sends any `switchcmd`, e.g. one added by a newer FRITZ!OS, and returns
+    /// the body of the response as is. See [`aha::pretty_xml`] for
+    /// displaying XML responses.
+    pub async fn raw_command(
+        &self,
+        switchcmd: &str,
+        ain: Option<&Ain>,
+        parameters: &[(String, String)],
+    ) -> Result<String, FritzerError> {
+        let command = RawCommand {
+            switchcmd,
+            ain,
+            parameters,
+        };
+
+        command.validate()?;
+
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .raw_command(transport, &sid, &command)
+                    .await
+            })
+            .await
+    }
+
+    pub async fn get_switches_by_ain(&self, ains: &[Ain]) -> Vec<BulkResult<Device>> {
+        bulk::run(ains, self.concurrency, |ain| self.get_switch(ain)).await
+    }
+
+    /// Returns whether the switch is on afterwards.
+    pub async fn set_switch(&self, ain: &Ain, action: SwitchAction) -> Result<bool, FritzerError> {
+        let transport = self.session.transport();
+
+        self.session
+            .with_session(|sid| async move {
+                self.switch_operator
+                    .set_switch(transport, &sid, ain, action)
+                    .await
+            })
+            .await
+    }
+
+    pub async fn set_switches(&self, ains: &[Ain], action: SwitchAction) -> Vec<BulkResult<bool>> {
+        bulk::run(ains, self.concurrency, |ain| self.set_switch(ain, action)).await
     }
 }
 
@@ -157,64 +398,92 @@ mod tests {
     use super::*;
 
     use async_trait::async_trait;
-    use std::error::Error;
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     use crate::command::Command;
     use crate::connection::{User, Users};
+    use crate::session::INVALID_SESSION;
+    use crate::transport::tests::FakeTransport;
 
     #[test]
-    fn fritzbox_is_connected_should_return_false_by_default() {
+    fn fritzbox_session_info_should_return_none_by_default() {
         // Arrange
         let url = Url::parse("http://localhost").expect("No valid URL.");
-        let fritzbox = Fritzbox::new(url);
+        let fritzbox = Fritzbox::builder(url).build().unwrap();
 
         // Act
 
         // Assert
-        assert_eq!(false, fritzbox.is_connected());
+        assert!(fritzbox.session_info().is_none());
     }
 
     #[tokio::test]
-    async fn fritzbox_is_connected_should_return_true_on_valid_sid() {
+    async fn fritzbox_connect_with_sid_should_return_connected_fritzbox_on_valid_sid() {
         // Arrange
         let url = Url::parse("http://localhost").expect("No valid URL.");
         let session_info = SessionInfo {
             sid: "1".repeat(16),
             challenge: String::new(),
+            block_time: 0,
             users: Users {
                 users: Vec::<User>::new(),
             },
         };
         let login = MockFritzboxLogin::with_session_info(&Some(session_info));
-        let mut fritzbox = Fritzbox::<MockFritzboxLogin>::with_login(url, login);
+        let fritzbox = Fritzbox::builder(url).login(login).build().unwrap();
 
         // Act
-        let _ = fritzbox.update_session_info().await;
+        let result = fritzbox.connect_with_sid(&"1".repeat(16)).await;
 
         // Assert
-        assert_eq!(true, fritzbox.is_connected());
+        assert!(result.unwrap().is_connected());
     }
 
     #[tokio::test]
-    async fn fritzbox_is_connected_should_return_false_on_invalid_sid() {
+    async fn fritzbox_connect_with_sid_should_fail_on_invalid_sid() {
         // Arrange
         let url = Url::parse("http://localhost").expect("No valid URL.");
         let session_info = SessionInfo {
             sid: INVALID_SESSION.to_string(),
             challenge: String::new(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let login = MockFritzboxLogin::with_session_info(&Some(session_info));
+        let fritzbox = Fritzbox::builder(url).login(login).build().unwrap();
+
+        // Act
+        let result = fritzbox.connect_with_sid(INVALID_SESSION).await;
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::NotConnected)));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_connect_with_credentials_should_fail_on_rejected_credentials() {
+        // Arrange
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: INVALID_SESSION.to_string(),
+            challenge:
+                "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0"
+                    .to_string(),
+            block_time: 0,
             users: Users {
                 users: Vec::<User>::new(),
             },
         };
         let login = MockFritzboxLogin::with_session_info(&Some(session_info));
-        let mut fritzbox =
-            Fritzbox::<MockFritzboxLogin, MockFritzboxSwitchOperator>::with_login(url, login);
+        let fritzbox = Fritzbox::builder(url).login(login).build().unwrap();
 
         // Act
-        let _ = fritzbox.update_session_info().await;
+        let result = fritzbox.connect_with_credentials("user", "password").await;
 
         // Assert
-        assert_eq!(false, fritzbox.is_connected());
+        assert!(matches!(result, Err(FritzerError::LoginFailed)));
     }
 
     #[tokio::test]
@@ -224,30 +493,33 @@ mod tests {
         let session_info = SessionInfo {
             sid: "1".repeat(16),
             challenge: String::new(),
+            block_time: 0,
             users: Users {
                 users: Vec::<User>::new(),
             },
         };
         let switches = vec![Device {
-            ain: "000001".to_string(),
+            ain: "087610000001".parse().unwrap(),
             name: "test1".to_string(),
         }];
         let login = MockFritzboxLogin::with_session_info(&Some(session_info));
         let switch_operator = MockFritzboxSwitchOperator::with_switches(switches);
-        let mut fritzbox =
-            Fritzbox::<MockFritzboxLogin, MockFritzboxSwitchOperator>::with_switchbox_operator(
-                url,
-                login,
-                switch_operator,
-            );
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
 
-        let _ = fritzbox.update_session_info().await;
+        let fritzbox = fritzbox.connect_with_sid(&"1".repeat(16)).await.unwrap();
 
         // Act
-        let result = fritzbox.get_switch("000001").await.unwrap();
+        let result = fritzbox
+            .get_switch(&"087610000001".parse().unwrap())
+            .await
+            .unwrap();
 
         // Assert
-        assert_eq!("000001", result.ain);
+        assert_eq!("087610000001", result.ain.as_str());
     }
 
     #[tokio::test]
@@ -257,30 +529,30 @@ mod tests {
         let session_info = SessionInfo {
             sid: "1".repeat(16),
             challenge: String::new(),
+            block_time: 0,
             users: Users {
                 users: Vec::<User>::new(),
             },
         };
         let switches = vec![
             Device {
-                ain: "000001".to_string(),
+                ain: "087610000001".parse().unwrap(),
                 name: "test1".to_string(),
             },
             Device {
-                ain: "000002".to_string(),
+                ain: "087610000002".parse().unwrap(),
                 name: "test2".to_string(),
             },
         ];
         let login = MockFritzboxLogin::with_session_info(&Some(session_info));
         let switch_operator = MockFritzboxSwitchOperator::with_switches(switches);
-        let mut fritzbox =
-            Fritzbox::<MockFritzboxLogin, MockFritzboxSwitchOperator>::with_switchbox_operator(
-                url,
-                login,
-                switch_operator,
-            );
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
 
-        let _ = fritzbox.update_session_info().await;
+        let fritzbox = fritzbox.connect_with_sid(&"1".repeat(16)).await.unwrap();
 
         // Act
         let result = fritzbox.get_switches().await.unwrap();
@@ -289,30 +561,318 @@ mod tests {
         assert_eq!(2, result.len());
     }
 
-    #[test]
-    fn fritzbox_get_challenge_response_should_return_valid_response() {
+    #[tokio::test]
+    async fn fritzbox_set_switches_should_report_result_per_device() {
+        // Arrange
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge: String::new(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let switches = vec![Device {
+            ain: "087610000001".parse().unwrap(),
+            name: "test1".to_string(),
+        }];
+        let ains: Vec<Ain> = vec![
+            "087610000001".parse().unwrap(),
+            "087610000002".parse().unwrap(),
+        ];
+        let login = MockFritzboxLogin::with_session_info(&Some(session_info));
+        let switch_operator = MockFritzboxSwitchOperator::with_switches(switches);
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
+
+        let fritzbox = fritzbox.connect_with_sid(&"1".repeat(16)).await.unwrap();
+
+        // Act
+        let results = fritzbox.set_switches(&ains, SwitchAction::On).await;
+
+        // Assert
+        assert!(results[0].result.as_ref().unwrap());
+        assert!(matches!(
+            results[1].result,
+            Err(FritzerError::DeviceNotPresent(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switches_should_read_names_of_listed_switches() {
         // Arrange
-        let challenge =
-            "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0";
-        let password = "my$uper$trongPa$$w0rd4U";
+        let session_info =
+            "<SessionInfo><SID>0123456789abcdef</SID><Challenge>2$10$ab$10$cd</Challenge>\
+             <BlockTime>0</BlockTime><Users><User>fritzer</User></Users></SessionInfo>";
+        let transport = FakeTransport::with_responses(vec![
+            (200, session_info),
+            (200, "087610000434,087610000435\n"),
+            (200, "Kettle\n"),
+            (200, "Desk\n"),
+        ]);
+        let fritzbox = Fritzbox::builder(Url::parse("http://localhost").unwrap())
+            .transport(transport)
+            .concurrency(1)
+            .build()
+            .unwrap();
+
+        let fritzbox = fritzbox.connect_with_sid("0123456789abcdef").await.unwrap();
 
         // Act
-        let response = Fritzbox::<FritzboxLogin>::get_challenge_response(&challenge, &password);
+        let result = fritzbox.get_switches().await.unwrap();
 
         // Assert
-        assert_eq!("d19cee81917f97da37430f45b8352db0%24506cf2017a1f3ff399bd66d750979ebdb0cc22fbdaa134acf2ad26c71df6c20f", response);
+        assert_eq!(2, result.len());
+        assert_eq!("087610000435", result[1].ain.as_str());
+        assert_eq!("Desk", result[1].name);
     }
 
-    impl Clone for SessionInfo {
-        fn clone(&self) -> Self {
-            Self {
-                sid: self.sid.clone(),
-                challenge: self.challenge.clone(),
-                users: Users {
-                    users: Vec::<User>::new(),
-                },
-            }
-        }
+    #[tokio::test]
+    async fn fritzbox_raw_command_should_reject_sid_parameter() {
+        // Arrange
+        let session_info =
+            "<SessionInfo><SID>0123456789abcdef</SID><Challenge>2$10$ab$10$cd</Challenge>\
+             <BlockTime>0</BlockTime><Users><User>fritzer</User></Users></SessionInfo>";
+        let transport = FakeTransport::with_responses(vec![(200, session_info)]);
+        let fritzbox = Fritzbox::builder(Url::parse("http://localhost").unwrap())
+            .transport(transport)
+            .build()
+            .unwrap();
+        let fritzbox = fritzbox.connect_with_sid("0123456789abcdef").await.unwrap();
+        let parameters = vec![("sid".to_string(), "0000000000000000".to_string())];
+
+        // Act
+        let result = fritzbox
+            .raw_command("getdevicelistinfos", None, &parameters)
+            .await;
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::InvalidValue(_))));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switch_should_reauthenticate_on_expired_session() {
+        // Arrange
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge:
+                "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0"
+                    .to_string(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let login = MockFritzboxLogin::with_session_info(&Some(session_info));
+        let switch_operator = ExpiringFritzboxSwitchOperator::with_expired_calls(1);
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
+
+        let fritzbox = fritzbox
+            .connect_with_credentials("user", "password")
+            .await
+            .unwrap();
+
+        // Act
+        let result = fritzbox.get_switch(&"087610000001".parse().unwrap()).await;
+
+        // Assert
+        assert!(result.is_ok());
+        assert_eq!(2, fritzbox.switch_operator.calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switch_should_fail_on_expired_session_without_credentials() {
+        // Arrange
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge: String::new(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let login = MockFritzboxLogin::with_session_info(&Some(session_info));
+        let switch_operator = ExpiringFritzboxSwitchOperator::with_expired_calls(2);
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
+        let fritzbox = fritzbox.connect_with_sid(&"1".repeat(16)).await.unwrap();
+
+        // Act
+        let result = fritzbox.get_switch(&"087610000001".parse().unwrap()).await;
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::NotConnected)));
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switch_should_read_password_on_expired_cached_session() {
+        // Arrange
+        std::env::set_var("FRITZER_TEST_REAUTHENTICATION_PASSWORD", "password");
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge:
+                "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0"
+                    .to_string(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let login = MockFritzboxLogin::with_session_info(&Some(session_info));
+        let switch_operator = ExpiringFritzboxSwitchOperator::with_expired_calls(1);
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
+        let fritzbox = fritzbox.connect_with_sid(&"1".repeat(16)).await.unwrap();
+
+        fritzbox.set_credential_source(
+            "user",
+            CredentialSource::Environment("FRITZER_TEST_REAUTHENTICATION_PASSWORD".to_string()),
+        );
+
+        // Act
+        let result = fritzbox.get_switch(&"087610000001".parse().unwrap()).await;
+
+        // Assert
+        assert!(result.is_ok());
+        assert!(fritzbox.derived_key().is_some());
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switch_should_report_renewed_session() {
+        // Arrange
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge:
+                "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0"
+                    .to_string(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let login = MockFritzboxLogin::with_session_info(&Some(session_info));
+        let switch_operator = ExpiringFritzboxSwitchOperator::with_expired_calls(1);
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
+        let fritzbox = fritzbox
+            .connect_with_credentials("user", "password")
+            .await
+            .unwrap();
+        let renewed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = renewed.clone();
+
+        fritzbox.on_session_renewal(move |sid| sink.lock().unwrap().push(sid.to_string()));
+
+        // Act
+        fritzbox
+            .get_switch(&"087610000001".parse().unwrap())
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(vec!["1".repeat(16)], *renewed.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switch_should_report_used_session() {
+        // Arrange
+        let switch = Device {
+            ain: "087610000001".parse().unwrap(),
+            name: "Kettle".to_string(),
+        };
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge: String::new(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let fritzbox = Fritzbox::builder(Url::parse("http://localhost").unwrap())
+            .login(MockFritzboxLogin::with_session_info(&Some(session_info)))
+            .switch_operator(MockFritzboxSwitchOperator::with_switches(vec![switch]))
+            .build()
+            .unwrap();
+        let fritzbox = fritzbox.connect_with_sid(&"1".repeat(16)).await.unwrap();
+        let used = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = used.clone();
+
+        fritzbox.on_session_use(move |sid| sink.lock().unwrap().push(sid.to_string()));
+
+        // Act
+        let found = fritzbox.get_switch(&"087610000001".parse().unwrap()).await;
+        let missing = fritzbox.get_switch(&"087610000002".parse().unwrap()).await;
+
+        // Assert
+        assert!(found.is_ok());
+        assert!(missing.is_err());
+        assert_eq!(vec!["1".repeat(16)], *used.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn fritzbox_get_switch_should_read_password_on_rejected_derived_key() {
+        // Arrange
+        std::env::set_var("FRITZER_TEST_REJECTED_KEY_PASSWORD", "password");
+        let url = Url::parse("http://localhost").expect("No valid URL.");
+        let session_info = SessionInfo {
+            sid: "1".repeat(16),
+            challenge: "2$10$ab$10$cd".to_string(),
+            block_time: 0,
+            users: Users {
+                users: Vec::<User>::new(),
+            },
+        };
+        let login = ChangingFritzboxLogin::with_session_info(session_info.clone());
+        let box_session_info = login.session_info.clone();
+        let switch_operator = ExpiringFritzboxSwitchOperator::with_expired_calls(1);
+        let fritzbox = Fritzbox::builder(url)
+            .login(login)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap();
+        let key = DerivedKey::derive("password", NonZeroU32::new(10).unwrap(), &[0xab]);
+        let fritzbox = fritzbox
+            .connect_with_derived_key("user", key)
+            .await
+            .unwrap();
+
+        fritzbox.set_credential_source(
+            "user",
+            CredentialSource::Environment("FRITZER_TEST_REJECTED_KEY_PASSWORD".to_string()),
+        );
+        box_session_info.lock().unwrap().challenge = "2$10$ef$10$cd".to_string();
+
+        // Act
+        let result = fritzbox.get_switch(&"087610000001".parse().unwrap()).await;
+
+        // Assert
+        assert!(result.is_ok());
+        assert!(fritzbox
+            .derived_key()
+            .unwrap()
+            .encode()
+            .starts_with("10$ef$"));
     }
 
     pub struct MockFritzboxLogin {
@@ -335,32 +895,74 @@ mod tests {
     impl Login for MockFritzboxLogin {
         async fn get_session_info(
             &self,
-            _client: &reqwest::Client,
-            _url: &Url,
-        ) -> Result<Option<SessionInfo>, Box<dyn Error>> {
+            _transport: &dyn Transport,
+        ) -> Result<Option<SessionInfo>, FritzerError> {
             Ok(self.session_info.clone())
         }
 
         async fn connect_with_sid(
             &self,
-            _client: &reqwest::Client,
-            _url: &Url,
+            _transport: &dyn Transport,
             _sid: &str,
-        ) -> Result<Option<SessionInfo>, Box<dyn Error>> {
+        ) -> Result<Option<SessionInfo>, FritzerError> {
             Ok(self.session_info.clone())
         }
 
         async fn connect_with_credentials(
             &self,
-            _client: &reqwest::Client,
-            _url: &Url,
+            _transport: &dyn Transport,
             _username: &str,
             _response: &str,
-        ) -> Result<Option<SessionInfo>, Box<dyn Error>> {
+        ) -> Result<Option<SessionInfo>, FritzerError> {
             Ok(self.session_info.clone())
         }
     }
 
+    /// A login whose session info, e.g. the challenge, can change between
+    /// logins.
+    pub struct ChangingFritzboxLogin {
+        session_info: Arc<std::sync::Mutex<SessionInfo>>,
+    }
+
+    impl ChangingFritzboxLogin {
+        fn with_session_info(session_info: SessionInfo) -> ChangingFritzboxLogin {
+            ChangingFritzboxLogin {
+                session_info: Arc::new(std::sync::Mutex::new(session_info)),
+            }
+        }
+    }
+
+    impl Command for ChangingFritzboxLogin {
+        const COMMAND_PATH: &'static str = "";
+    }
+
+    #[async_trait]
+    impl Login for ChangingFritzboxLogin {
+        async fn get_session_info(
+            &self,
+            _transport: &dyn Transport,
+        ) -> Result<Option<SessionInfo>, FritzerError> {
+            Ok(Some(self.session_info.lock().unwrap().clone()))
+        }
+
+        async fn connect_with_sid(
+            &self,
+            _transport: &dyn Transport,
+            _sid: &str,
+        ) -> Result<Option<SessionInfo>, FritzerError> {
+            Ok(Some(self.session_info.lock().unwrap().clone()))
+        }
+
+        async fn connect_with_credentials(
+            &self,
+            _transport: &dyn Transport,
+            _username: &str,
+            _response: &str,
+        ) -> Result<Option<SessionInfo>, FritzerError> {
+            Ok(Some(self.session_info.lock().unwrap().clone()))
+        }
+    }
+
     pub struct MockFritzboxSwitchOperator {
         switches: Vec<Device>,
     }
@@ -379,21 +981,90 @@ mod tests {
     impl SwitchOperator for MockFritzboxSwitchOperator {
         async fn get_switch(
             &self,
-            _client: &reqwest::Client,
-            _url: &Url,
+            _transport: &dyn Transport,
+            _sid: &str,
+            ain: &Ain,
+        ) -> Result<Device, FritzerError> {
+            self.switches
+                .iter()
+                .find(|s| s.ain == *ain)
+                .cloned()
+                .ok_or_else(|| FritzerError::DeviceNotPresent(ain.to_string()))
+        }
+
+        async fn get_switch_list(
+            &self,
+            _transport: &dyn Transport,
+            _sid: &str,
+        ) -> Result<Vec<Ain>, FritzerError> {
+            Ok(self.switches.iter().map(|s| s.ain.clone()).collect())
+        }
+
+        async fn set_switch(
+            &self,
+            transport: &dyn Transport,
+            sid: &str,
+            ain: &Ain,
+            action: SwitchAction,
+        ) -> Result<bool, FritzerError> {
+            self.get_switch(transport, sid, ain).await?;
+
+            Ok(action != SwitchAction::Off)
+        }
+    }
+
+    pub struct ExpiringFritzboxSwitchOperator {
+        expired_calls: usize,
+        calls: AtomicUsize,
+    }
+
+    impl ExpiringFritzboxSwitchOperator {
+        fn with_expired_calls(expired_calls: usize) -> ExpiringFritzboxSwitchOperator {
+            ExpiringFritzboxSwitchOperator {
+                expired_calls,
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl Command for ExpiringFritzboxSwitchOperator {
+        const COMMAND_PATH: &'static str = "";
+    }
+
+    #[async_trait]
+    impl SwitchOperator for ExpiringFritzboxSwitchOperator {
+        async fn get_switch(
+            &self,
+            _transport: &dyn Transport,
+            _sid: &str,
+            ain: &Ain,
+        ) -> Result<Device, FritzerError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.expired_calls {
+                return Err(FritzerError::NotConnected);
+            }
+
+            Ok(Device {
+                ain: ain.clone(),
+                name: "test1".to_string(),
+            })
+        }
+
+        async fn get_switch_list(
+            &self,
+            _transport: &dyn Transport,
             _sid: &str,
-            _ain: &str,
-        ) -> Result<Device, Box<dyn Error>> {
-            Ok(self.switches[0].clone())
+        ) -> Result<Vec<Ain>, FritzerError> {
+            Ok(Vec::new())
         }
 
-        async fn get_switches(
+        async fn set_switch(
             &self,
-            _client: &reqwest::Client,
-            _url: &Url,
+            _transport: &dyn Transport,
             _sid: &str,
-        ) -> Result<Vec<Device>, Box<dyn Error>> {
-            Ok(self.switches.clone())
+            _ain: &Ain,
+            _action: SwitchAction,
+        ) -> Result<bool, FritzerError> {
+            Ok(true)
         }
     }
 }
@@ -0,0 +1,218 @@
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record};
+use simple_logger::SimpleLogger;
+use std::{env, io::Write};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Query and form parameters whose values are secret.
+const SECRET_KEYS: [&str; 2] = ["sid=", "response="];
+
+const REDACTED: &str = "REDACTED";
+
+/// The SID of no session, which is safe to log.
+const INVALID_SID: &str = "0000000000000000";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with time, level, target and message
+    Json,
+}
+
+/// Replaces SIDs, which are 16 hexadecimal digits, and the values of
+/// `sid=` and `response=` parameters.
+fn redact(message: &str) -> String {
+    let mut output = String::with_capacity(message.len());
+    let mut i = 0;
+
+    while let Some(c) = message[i..].chars().next() {
+        let rest = &message[i..];
+        let boundary = message[..i]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_ascii_alphanumeric());
+
+        if boundary {
+            if let Some(key) = SECRET_KEYS.iter().find(|key| rest.starts_with(**key)) {
+                let value = &rest[key.len()..];
+                let length = value
+                    .find(|c: char| {
+                        c == '&' || c == '"' || c == '\'' || c == '<' || c.is_whitespace()
+                    })
+                    .unwrap_or(value.len());
+
+                if length > 0 && &value[..length] != INVALID_SID {
+                    output.push_str(key);
+                    output.push_str(REDACTED);
+                    i += key.len() + length;
+                    continue;
+                }
+            }
+
+            let length = rest
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(rest.len());
+            let token = &rest[..length];
+
+            if length == 16
+                && token != INVALID_SID
+                && !rest[length..].starts_with(char::is_alphanumeric)
+            {
+                output.push_str(REDACTED);
+                i += length;
+                continue;
+            }
+        }
+
+        output.push(c);
+        i += c.len_utf8();
+    }
+
+    output
+}
+
+/// The level of `-v` and `-q`: warnings by default, `-v` adds info, `-vv`
+/// debug and `-vvv` trace, `-q` only shows errors and `-qq` nothing.
+fn verbosity_level(verbose: u8, quiet: u8) -> LevelFilter {
+    match i16::from(verbose) - i16::from(quiet) {
+        ..=-2 => LevelFilter::Off,
+        -1 => LevelFilter::Error,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Applies directives in the syntax of `RUST_LOG`, e.g.
+/// `info,libfritzer=debug,reqwest=off`. Invalid directives are ignored.
+fn with_directives(mut logger: SimpleLogger, directives: &str) -> SimpleLogger {
+    for directive in directives.split(',').map(str::trim) {
+        logger = match directive.split_once('=') {
+            Some((target, level)) => match level.parse() {
+                Ok(level) => logger.with_module_level(target, level),
+                Err(_) => logger,
+            },
+            None if directive.is_empty() => logger,
+            None => match directive.parse() {
+                Ok(level) => logger.with_level(level),
+                Err(_) => logger.with_module_level(directive, LevelFilter::Trace),
+            },
+        };
+    }
+
+    logger
+}
+
+/// Filters like [`SimpleLogger`] and redacts every message before writing it.
+struct Logger {
+    inner: SimpleLogger,
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = redact(&record.args().to_string());
+
+        match self.format {
+            LogFormat::Text => self.inner.log(
+                &Record::builder()
+                    .metadata(record.metadata().clone())
+                    .args(format_args!("{}", message))
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            LogFormat::Json => {
+                let line = serde_json::json!({
+                    "time": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message,
+                });
+                let _ = writeln!(std::io::stderr().lock(), "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger. `-v` and `-q` set the level of fritzer and keep other
+/// crates at warnings or less. Without them, `RUST_LOG` is used if set.
+pub fn init(verbose: u8, quiet: u8, format: LogFormat) {
+    let level = verbosity_level(verbose, quiet);
+    let inner = match env::var("RUST_LOG") {
+        Ok(directives) if verbose == 0 && quiet == 0 => {
+            with_directives(SimpleLogger::new().with_level(level), &directives)
+        }
+        _ => SimpleLogger::new()
+            .with_level(level.min(LevelFilter::Warn))
+            .with_module_level("fritzer", level)
+            .with_module_level("libfritzer", level),
+    };
+    let max_level = inner.max_level();
+
+    if log::set_boxed_logger(Box::new(Logger { inner, format })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_should_replace_sids_and_responses() {
+        // Arrange
+        let messages = [
+            "GET /webservices/homeautoswitch.lua?switchcmd=getswitchlist&sid=0123456789abcdef",
+            "POST /login_sid.lua?version=2 username=fritzer&response=4f8c$1d2e3f",
+            r#"SessionInfo { sid: "0123456789abcdef", challenge: "2$10$ab$10$cd" }"#,
+            "<SID>0000000000000000</SID> for 087610000434",
+        ];
+
+        // Act
+        let redacted: Vec<String> = messages.iter().map(|m| redact(m)).collect();
+
+        // Assert
+        assert_eq!(
+            vec![
+                "GET /webservices/homeautoswitch.lua?switchcmd=getswitchlist&sid=REDACTED",
+                "POST /login_sid.lua?version=2 username=fritzer&response=REDACTED",
+                r#"SessionInfo { sid: "REDACTED", challenge: "2$10$ab$10$cd" }"#,
+                "<SID>0000000000000000</SID> for 087610000434",
+            ],
+            redacted
+        );
+    }
+
+    #[test]
+    fn with_directives_should_set_default_and_module_levels() {
+        // Arrange
+        let logger = SimpleLogger::new().with_level(LevelFilter::Warn);
+
+        // Act
+        let logger = with_directives(logger, "error, libfritzer=debug,reqwest=nonsense");
+
+        // Assert
+        let enabled = |target: &str, level| {
+            logger.enabled(&Metadata::builder().target(target).level(level).build())
+        };
+        assert!(enabled("libfritzer::session", log::Level::Debug));
+        assert!(!enabled("fritzer", log::Level::Warn));
+        assert!(enabled("reqwest::connect", log::Level::Error));
+        assert_eq!(LevelFilter::Debug, logger.max_level());
+    }
+}
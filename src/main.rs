@@ -1,32 +1,173 @@
-use clap::{Parser, Subcommand};
-use libfritzer::{command::Device, Fritzbox};
-use log::{debug, info, warn, Level};
+use clap::{error::ErrorKind, ArgAction, ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use libfritzer::{
+    aha,
+    ain::Ain,
+    builder::FritzboxBuilder,
+    bulk::BulkResult,
+    command::{Device, FritzboxLogin, FritzboxSwitchOperator, SwitchAction, SwitchOperator},
+    config::{Config, Profile},
+    credentials::{CredentialSource, PASSWORD_ENV_VAR},
+    device::{DeviceInfo, Setpoint},
+    dry_run::RecordingSwitchOperator,
+    error::FritzerError,
+    output::{write_csv_rows, write_rows, OutputFormat},
+    retry::RetryPolicy,
+    session::DerivedKey,
+    sid_cache::SidCache,
+    target,
+    target::Target,
+    tls::Fingerprint,
+    watch::{self, Change, Thresholds},
+};
+use log::{debug, error, info, warn};
+use serde::Serialize;
 use std::{
-    env,
-    fs::File,
-    io::{Read, Write},
-    path::{Path, PathBuf},
+    env, fs, io,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use url::Url;
 
+mod batch;
+mod logger;
+mod shell;
+mod tui;
+
+/// The switch operator is chosen at runtime, see `--dry-run`.
+type Fritzbox = libfritzer::Fritzbox<FritzboxLogin, Box<dyn SwitchOperator>>;
+type ConnectedFritzbox = libfritzer::ConnectedFritzbox<FritzboxLogin, Box<dyn SwitchOperator>>;
+
+/// The values of `--output`, mapped to [`OutputFormat`] here so that
+/// libfritzer does not depend on clap.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputArg {
+    /// Aligned columns for humans
+    Table,
+    /// One JSON array
+    Json,
+    /// Comma-separated values with a header line
+    Csv,
+    /// One YAML sequence
+    Yaml,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl From<OutputArg> for OutputFormat {
+    fn from(output: OutputArg) -> Self {
+        match output {
+            OutputArg::Table => OutputFormat::Table,
+            OutputArg::Json => OutputFormat::Json,
+            OutputArg::Csv => OutputFormat::Csv,
+            OutputArg::Yaml => OutputFormat::Yaml,
+            OutputArg::Ndjson => OutputFormat::Ndjson,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author = "fritzer", version = "0.1", about = "Use FRITZ!Box AHA interface", long_about = None)]
 struct Args {
+    /// Path to the configuration file (default: ~/.config/fritzer/config.toml)
+    #[arg(short, long, value_name = "FILE", env = "FRITZER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Profile of the configuration file (default: default_profile of the configuration file)
+    #[arg(short = 'P', long, env = "FRITZER_PROFILE")]
+    profile: Option<String>,
+
     /// Url of the FRITZ!Box
-    #[arg(short, long)]
-    url: Url,
+    #[arg(short, long, env = "FRITZER_URL")]
+    url: Option<Url>,
+
+    /// Request timeout in seconds, 0 waits forever (default: 30)
+    #[arg(long, value_name = "SECONDS", env = "FRITZER_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Number of retries of requests failing while the FRITZ!Box is busy, 0 disables retries (default: 2)
+    #[arg(long, value_name = "N", env = "FRITZER_RETRIES")]
+    retries: Option<u32>,
+
+    /// Maximum number of parallel requests when operating on several devices (default: 4)
+    #[arg(long, value_name = "N", env = "FRITZER_CONCURRENCY")]
+    concurrency: Option<usize>,
+
+    /// HTTP proxy for all requests to the FRITZ!Box
+    #[arg(long, value_name = "URL", env = "FRITZER_PROXY")]
+    proxy: Option<Url>,
+
+    /// PEM file of a CA certificate to trust for HTTPS
+    #[arg(long, value_name = "FILE", env = "FRITZER_CA_FILE")]
+    ca_file: Option<PathBuf>,
+
+    /// Accept only the HTTPS certificate with this SHA-256 fingerprint (see `tls fetch-fingerprint`)
+    #[arg(long, value_name = "SHA256", env = "FRITZER_FINGERPRINT")]
+    fingerprint: Option<Fingerprint>,
+
+    /// Do not verify the HTTPS certificate (exposes the session to eavesdroppers!)
+    #[arg(long, conflicts_with_all = ["ca_file", "fingerprint"])]
+    insecure: bool,
+
+    /// Directory of the session cache (default: $XDG_CACHE_HOME/fritzer)
+    #[arg(short, long, value_name = "DIR", env = "FRITZER_SID_CACHE")]
+    sid_cache: Option<PathBuf>,
+
+    /// Do not read or write cached sessions
+    #[arg(long, conflicts_with = "sid_cache")]
+    no_sid_cache: bool,
 
-    /// Path to the session file (default: ~/.fritzer.sid)
-    #[arg(short, long, value_name = "FILE")]
-    sid_path: Option<PathBuf>,
+    /// Also cache the PBKDF2-derived key so that logins need no password (password-equivalent!)
+    #[arg(long, conflicts_with = "no_sid_cache")]
+    cache_derived_key: bool,
 
     /// FRITZ!Box user (default: last logged in user)
-    #[arg(long)]
+    #[arg(long, env = "FRITZER_USERNAME")]
     username: Option<String>,
 
     /// Path to the file containing the password
-    #[arg(short, long)]
-    password: Option<String>,
+    #[arg(
+        short = 'p',
+        long,
+        value_name = "FILE",
+        alias = "password",
+        env = "FRITZER_PASSWORD_FILE"
+    )]
+    password_file: Option<PathBuf>,
+
+    /// Command printing the password on its first line (e.g. `pass show fritzbox`)
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        conflicts_with = "password_file",
+        env = "FRITZER_PASSWORD_COMMAND"
+    )]
+    password_command: Option<String>,
+
+    /// Read the password from the first line of stdin
+    #[arg(long, conflicts_with_all = ["password_file", "password_command"])]
+    password_stdin: bool,
+
+    /// Print the requests of commands changing devices instead of sending them
+    #[arg(long, env = "FRITZER_DRY_RUN")]
+    dry_run: bool,
+
+    /// Log more: -v info, -vv debug, -vvv trace (default: warnings, or RUST_LOG)
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Log less: -q errors only, -qq nothing
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// Format of log messages on stderr
+    #[arg(long, value_enum, default_value_t, env = "FRITZER_LOG_FORMAT")]
+    log_format: logger::LogFormat,
+
+    /// Output format of all commands (default: output of the profile, or table)
+    #[arg(short, long, value_enum, env = "FRITZER_OUTPUT")]
+    output: Option<OutputArg>,
 
     #[command(subcommand)]
     command: Option<Commands>,
@@ -35,194 +176,1228 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Commands related to switches
+    #[command(
+        group(ArgGroup::new("action").args(["list", "ain"]).required(true)),
+        subcommand_negates_reqs = true
+    )]
     Switch {
         /// lists switches
         #[arg(short, long)]
         list: bool,
+
+        /// shows the given switches (AIN, name, name prefix, glob, tag:<NAME> or all)
+        #[arg(short, long, value_name = "TARGET", num_args = 1.., conflicts_with = "list")]
+        ain: Vec<Target>,
+
+        #[command(subcommand)]
+        command: Option<SwitchCommands>,
+    },
+    /// Prints changes of the devices until interrupted
+    Watch {
+        /// Seconds between two polls
+        #[arg(short, long, value_name = "SECONDS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Minimum change of the power in watts to report
+        #[arg(long, value_name = "WATTS", default_value_t = Thresholds::default().power)]
+        power_threshold: f64,
+
+        /// Minimum change of the temperature in °C to report
+        #[arg(long, value_name = "CELSIUS", default_value_t = Thresholds::default().temperature)]
+        temperature_threshold: f64,
+
+        /// Devices to watch (default: all)
+        #[arg(value_name = "TARGET")]
+        targets: Vec<Target>,
+    },
+    /// Shows all devices in an interactive dashboard
+    Tui {
+        /// Seconds between two refreshes
+        #[arg(short, long, value_name = "SECONDS", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Commands related to all kinds of devices
+    Device {
+        #[command(subcommand)]
+        command: DeviceCommands,
+    },
+    /// Commands related to thermostats
+    Thermostat {
+        #[command(subcommand)]
+        command: ThermostatCommands,
+    },
+    /// Commands related to templates
+    Template {
+        #[command(subcommand)]
+        command: TemplateCommands,
+    },
+    /// Sends any AHA command and prints the response as is
+    Raw {
+        /// The switchcmd, e.g. getdevicelistinfos
+        #[arg(value_name = "SWITCHCMD")]
+        switchcmd: String,
+
+        /// AIN of the device, group or template
+        #[arg(long)]
+        ain: Option<Ain>,
+
+        /// Indent XML responses
+        #[arg(long)]
+        pretty: bool,
+
+        /// Further parameters of the command
+        #[arg(value_name = "KEY=VALUE", value_parser = parse_parameter)]
+        parameters: Vec<(String, String)>,
+    },
+    /// Runs commands interactively in one session
+    Shell,
+    /// Runs the commands of a file, one per line, in one session
+    Batch {
+        /// File of commands, - for stdin (default: stdin)
+        #[arg(value_name = "FILE|-")]
+        file: Option<PathBuf>,
+
+        /// Run the remaining commands after a command failed
+        #[arg(long)]
+        continue_on_error: bool,
+    },
+    /// Commands related to HTTPS
+    Tls {
+        #[command(subcommand)]
+        command: TlsCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SwitchCommands {
+    /// Switches the given switches on
+    On {
+        /// AIN, name, name prefix, glob, tag:<NAME> or all
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<Target>,
+    },
+    /// Switches the given switches off
+    Off {
+        /// AIN, name, name prefix, glob, tag:<NAME> or all
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<Target>,
+    },
+    /// Toggles the given switches
+    Toggle {
+        /// AIN, name, name prefix, glob, tag:<NAME> or all
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<Target>,
+    },
+    /// Exits with 0 if all given switches are on, 1 otherwise, printing nothing
+    IsOn {
+        /// AIN, name, name prefix, glob, tag:<NAME> or all
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<Target>,
     },
 }
 
-async fn get_stored_sid(path: &Path) -> Option<String> {
-    let mut sid = String::new();
+#[derive(Subcommand, Debug)]
+enum DeviceCommands {
+    /// Renames a device or group
+    Rename {
+        /// AIN, name, name prefix or glob matching exactly one device
+        #[arg(value_name = "TARGET")]
+        target: Target,
 
-    match path.exists() {
-        true => {
-            info!("Reading SID from file...");
+        /// The new name
+        name: String,
+    },
+    /// Exits with 0 if all given devices are connected, 1 otherwise, printing nothing
+    IsPresent {
+        /// AIN, name, name prefix, glob, tag:<NAME> or all
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<Target>,
+    },
+}
 
-            let file = File::open(path);
-            let result = file.unwrap().read_to_string(&mut sid);
+#[derive(Subcommand, Debug)]
+enum ThermostatCommands {
+    /// Sets the target temperature of the given thermostats
+    Set {
+        /// AIN, name, name prefix, glob, tag:<NAME> or all
+        #[arg(value_name = "TARGET", required = true)]
+        targets: Vec<Target>,
 
-            match result {
-                Ok(_) => Some(sid),
-                Err(_) => None,
-            }
-        }
-        false => None,
+        /// off, on or degrees Celsius from 8 to 28 in steps of 0.5
+        #[arg(value_name = "SETPOINT")]
+        setpoint: Setpoint,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplateCommands {
+    /// Lists templates
+    List,
+    /// Applies the given templates
+    Apply {
+        /// Identifier, name, name prefix or glob
+        #[arg(value_name = "TEMPLATE", required = true)]
+        templates: Vec<Target>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TlsCommands {
+    /// Prints the SHA-256 fingerprint of the certificate of the FRITZ!Box without verifying it
+    FetchFingerprint,
+}
+
+impl Args {
+    /// `--output`, falling back to the profile and then to a table.
+    fn output(&self, profile: &Profile) -> OutputFormat {
+        self.output
+            .map(OutputFormat::from)
+            .or(profile.output)
+            .unwrap_or_default()
+    }
+}
+
+fn parse_parameter(parameter: &str) -> Result<(String, String), String> {
+    match parameter.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("'{}' is not of the form KEY=VALUE", parameter)),
     }
 }
 
-async fn get_password(arg_password: &Option<String>) -> String {
-    if arg_password.is_none() {
-        rpassword::prompt_password("Your password: ").unwrap()
+fn exit_with_error(kind: ErrorKind, message: impl std::fmt::Display) -> ! {
+    Args::command().error(kind, message).exit()
+}
+
+fn get_profile(args: &Args) -> Profile {
+    let config = match (&args.config, Config::default_path()) {
+        (Some(path), _) => Config::load(path).unwrap_or_else(|e| {
+            exit_with_error(
+                ErrorKind::Io,
+                format!("Unable to read {}: {}", path.display(), e),
+            )
+        }),
+        (None, Some(path)) if path.exists() => Config::load(&path).unwrap_or_else(|e| {
+            exit_with_error(
+                ErrorKind::Io,
+                format!("Unable to read {}: {}", path.display(), e),
+            )
+        }),
+        (None, _) => Config::default(),
+    };
+
+    config
+        .profile(args.profile.as_deref())
+        .unwrap_or_else(|e| exit_with_error(ErrorKind::InvalidValue, e))
+}
+
+fn get_url(args: &Args, profile: &Profile) -> Url {
+    match args.url.as_ref().or(profile.url.as_ref()) {
+        Some(url) => url.clone(),
+        None => exit_with_error(
+            ErrorKind::MissingRequiredArgument,
+            "No FRITZ!Box URL given. Use --url, FRITZER_URL or a profile.",
+        ),
+    }
+}
+
+fn get_fritzbox_builder(
+    args: &Args,
+    profile: &Profile,
+    url: &Url,
+) -> Result<FritzboxBuilder, FritzerError> {
+    let mut builder = FritzboxBuilder::new(url.clone());
+
+    match args.timeout.or(profile.timeout) {
+        Some(0) => builder = builder.timeout(None),
+        Some(seconds) => builder = builder.timeout(Some(Duration::from_secs(seconds))),
+        None => {}
+    }
+
+    if let Some(concurrency) = args.concurrency.or(profile.concurrency) {
+        builder = builder.concurrency(concurrency);
+    }
+
+    if let Some(retries) = args.retries.or(profile.retries) {
+        builder = builder.retry_policy(RetryPolicy::with_retries(retries));
+    }
+
+    if let Some(proxy) = args.proxy.as_ref().or(profile.proxy.as_ref()) {
+        builder = builder.proxy(proxy.clone());
+    }
+
+    // TLS flags replace the TLS settings of the profile as a whole.
+    let (insecure, fingerprint, ca_file) =
+        if args.insecure || args.fingerprint.is_some() || args.ca_file.is_some() {
+            (args.insecure, args.fingerprint, args.ca_file.as_ref())
+        } else {
+            (
+                profile.insecure,
+                profile.fingerprint,
+                profile.ca_file.as_ref(),
+            )
+        };
+
+    if insecure {
+        warn!("HTTPS certificate verification is disabled.");
+
+        builder = builder.danger_accept_invalid_certificates(true);
+    }
+
+    if let Some(fingerprint) = fingerprint {
+        builder = builder.pin_certificate(fingerprint);
+    }
+
+    if let Some(path) = ca_file {
+        let pem = fs::read(path)
+            .map_err(|e| FritzerError::Tls(format!("Unable to read {}: {}", path.display(), e)))?;
+
+        builder = builder.ca_certificate(&pem);
+    }
+
+    Ok(builder)
+}
+
+fn get_credential_source(args: &Args, profile: &Profile) -> CredentialSource {
+    if let Some(path) = &args.password_file {
+        CredentialSource::File(path.clone())
+    } else if let Some(command) = &args.password_command {
+        CredentialSource::Command(command.clone())
+    } else if args.password_stdin {
+        CredentialSource::Stdin
+    } else if env::var_os(PASSWORD_ENV_VAR).is_some() {
+        CredentialSource::Environment(PASSWORD_ENV_VAR.to_string())
     } else {
-        arg_password.as_ref().unwrap().to_string()
+        profile
+            .credential_source()
+            .unwrap_or(CredentialSource::Prompt)
     }
 }
 
-async fn store_sid(fritzbox: &Fritzbox, path: &Path) -> Result<(), std::io::Error> {
-    let session_info = fritzbox.session_info.as_ref().unwrap();
-    let file = File::create(path);
+fn get_sid_cache(args: &Args, profile: &Profile) -> Option<SidCache> {
+    if args.no_sid_cache || (args.sid_cache.is_none() && profile.no_sid_cache) {
+        return None;
+    }
+
+    let directory = args
+        .sid_cache
+        .clone()
+        .or_else(|| profile.sid_cache.clone())
+        .or_else(SidCache::default_directory)?;
 
-    file.unwrap().write_all(session_info.sid.as_bytes())
+    Some(SidCache::new(directory))
 }
 
-async fn connect_to_fritzbox_with_credentials(
-    fritzbox: &mut Fritzbox,
-    username: &Option<String>,
-    password: &Option<String>,
-    path_to_stored_sid: &Path,
-) {
-    let session_info = fritzbox.session_info.as_ref().unwrap();
+fn get_username(fritzbox: &Fritzbox, username: &Option<String>) -> Result<String, FritzerError> {
+    if let Some(username) = username {
+        return Ok(username.clone());
+    }
+
+    let session_info = fritzbox.session_info().ok_or(FritzerError::NotConnected)?;
     let user = session_info
         .users
         .users
         .iter()
         .find(|u| u.last.is_some() && u.last.unwrap() == 1);
-    let username = match user {
-        Some(u) => u.username.clone(),
-        None => {
-            if username.is_none() {
-                panic!("No username available.");
-            }
 
-            username.as_ref().unwrap().clone()
+    match user {
+        Some(u) => Ok(u.username.clone()),
+        None => exit_with_error(
+            ErrorKind::MissingRequiredArgument,
+            "No username available. Use --username, FRITZER_USERNAME or a profile.",
+        ),
+    }
+}
+
+async fn connect_to_fritzbox_with_sid(fritzbox: &Fritzbox, sid: &str) -> Option<ConnectedFritzbox> {
+    match fritzbox.connect_with_sid(sid).await {
+        Ok(connected_fritzbox) => {
+            info!("Cached SID still valid. Re-use...");
+
+            Some(connected_fritzbox)
         }
-    };
-    let password = get_password(password).await;
+        Err(FritzerError::NotConnected) => {
+            info!("Cached SID invalid. Request new SID...");
 
-    let result = fritzbox
-        .connect_with_credentials(&username, &password)
-        .await;
+            None
+        }
+        Err(e) => {
+            debug!("Could not validate SID due to the following error {:?}", e);
 
-    if result.is_err() {
-        panic!("Unable to connect to Fritzbox!");
+            None
+        }
     }
+}
+
+async fn connect_to_fritzbox_with_credentials(
+    fritzbox: &Fritzbox,
+    username: &str,
+    credential_source: &CredentialSource,
+) -> Result<ConnectedFritzbox, FritzerError> {
+    let password = credential_source.read_password()?;
+
+    fritzbox.connect_with_credentials(username, &password).await
+}
 
-    let result = store_sid(&fritzbox, &path_to_stored_sid).await;
+async fn connect_to_fritzbox_with_derived_key(
+    fritzbox: &Fritzbox,
+    username: &str,
+    key: DerivedKey,
+) -> Option<ConnectedFritzbox> {
+    match fritzbox.connect_with_derived_key(username, key).await {
+        Ok(connected_fritzbox) => {
+            info!("Cached derived key still valid. Re-use...");
 
-    if result.is_err() {
-        warn!("Unable to cache SID.");
+            Some(connected_fritzbox)
+        }
+        Err(e) => {
+            debug!("Could not log in with derived key due to {:?}", e);
+
+            None
+        }
     }
 }
 
+/// How often the last use of a cached SID is updated while the session is in
+/// use, e.g. during `watch`.
+const SID_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 async fn connect_to_fritzbox(
+    mut fritzbox: Fritzbox,
     url: &Url,
     username: &Option<String>,
-    password: &Option<String>,
-    sid_path: &Option<PathBuf>,
-) -> Fritzbox {
-    let mut fritzbox = Fritzbox::new(url.clone());
-
-    let result = fritzbox.update_session_info().await;
+    credential_source: &CredentialSource,
+    sid_cache: &Option<SidCache>,
+    cache_derived_key: bool,
+) -> Result<ConnectedFritzbox, FritzerError> {
+    fritzbox.update_session_info().await?;
 
-    if result.is_err() {
-        panic!(
-            "Unable to receive session information from Fritzbox at {}!",
-            url
-        );
-    }
-
-    debug!("Session info: {:?}", fritzbox.session_info);
-    let backup_path_to_stored_sid =
-        PathBuf::from(format!("{}/.fritzer.sid", env::var("HOME").unwrap()));
-    let path_to_stored_sid = sid_path.as_ref().unwrap_or(&backup_path_to_stored_sid);
-    let stored_sid = get_stored_sid(&path_to_stored_sid).await;
+    let username = get_username(&fritzbox, username)?;
+    let stored_sid = sid_cache.as_ref().and_then(|c| c.load(url, &username));
 
-    match stored_sid {
+    let mut connected_fritzbox = match stored_sid {
         None => {
             info!("No cached SID available. Request new SID...");
 
-            connect_to_fritzbox_with_credentials(
-                &mut fritzbox,
-                username,
-                password,
-                &path_to_stored_sid,
-            )
-            .await;
+            None
         }
         Some(sid) => {
-            let result = fritzbox.connect_with_sid(&sid).await;
-
-            if result.is_err() {
-                debug!(
-                    "Could not validate SID due to the following error {:?}",
-                    result
-                );
-
-                connect_to_fritzbox_with_credentials(
-                    &mut fritzbox,
-                    username,
-                    password,
-                    &path_to_stored_sid,
-                )
-                .await;
-            } else {
-                let is_connected = result.unwrap();
-
-                if is_connected {
-                    info!("Cached SID still valid. Re-use...");
-                } else {
-                    info!("Cached SID invalid. Request new SID...");
-
-                    connect_to_fritzbox_with_credentials(
-                        &mut fritzbox,
-                        username,
-                        password,
-                        &path_to_stored_sid,
-                    )
-                    .await;
+            let connected_fritzbox = connect_to_fritzbox_with_sid(&fritzbox, &sid).await;
+
+            if let (None, Some(sid_cache)) = (&connected_fritzbox, sid_cache) {
+                if sid_cache.remove(url, &username).is_err() {
+                    warn!("Unable to remove cached SID.");
                 }
             }
+
+            connected_fritzbox
+        }
+    };
+
+    if connected_fritzbox.is_none() && cache_derived_key {
+        if let Some(key) = sid_cache
+            .as_ref()
+            .and_then(|c| c.load_derived_key(url, &username))
+        {
+            connected_fritzbox =
+                connect_to_fritzbox_with_derived_key(&fritzbox, &username, key).await;
+        }
+    }
+
+    let connected_fritzbox = match connected_fritzbox {
+        Some(connected_fritzbox) => connected_fritzbox,
+        None => {
+            connect_to_fritzbox_with_credentials(&fritzbox, &username, credential_source).await?
+        }
+    };
+
+    // A session from a cached SID holds no credentials to log in again.
+    connected_fritzbox.set_credential_source(&username, credential_source.clone());
+
+    if let Some(sid_cache) = sid_cache {
+        let sid = connected_fritzbox.sid().ok_or(FritzerError::NotConnected)?;
+        let (cache, cache_url, cache_username) = (sid_cache.clone(), url.clone(), username.clone());
+
+        connected_fritzbox.on_session_renewal(move |sid| {
+            if cache.store(&cache_url, &cache_username, sid).is_err() {
+                warn!("Unable to cache renewed SID.");
+            }
+        });
+
+        let (cache, cache_url, cache_username) = (sid_cache.clone(), url.clone(), username.clone());
+        let last_refresh = Mutex::new(Instant::now());
+
+        // Every request extends the session, so refresh its last use now and then.
+        connected_fritzbox.on_session_use(move |sid| {
+            let mut last_refresh = last_refresh.lock().unwrap();
+
+            if last_refresh.elapsed() >= SID_REFRESH_INTERVAL {
+                *last_refresh = Instant::now();
+
+                if cache.store(&cache_url, &cache_username, sid).is_err() {
+                    warn!("Unable to refresh cached SID.");
+                }
+            }
+        });
+
+        if sid_cache.store(url, &username, &sid).is_err() {
+            warn!("Unable to cache SID.");
+        }
+
+        let result = match connected_fritzbox.derived_key() {
+            Some(key) if cache_derived_key => sid_cache.store_derived_key(url, &username, &key),
+            Some(_) => sid_cache.remove_derived_key(url, &username),
+            None => Ok(()),
+        };
+
+        if result.is_err() {
+            warn!("Unable to update cached derived key.");
+        }
+    }
+
+    Ok(connected_fritzbox)
+}
+
+#[derive(Serialize)]
+struct FingerprintRow {
+    fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct DeviceRow {
+    ain: Ain,
+    name: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SwitchRow {
+    ain: Ain,
+    on: Option<bool>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ThermostatRow {
+    ain: Ain,
+    setpoint: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RawRow<'a> {
+    switchcmd: &'a str,
+    ain: Option<&'a Ain>,
+    body: String,
+}
+
+#[derive(Serialize)]
+struct ChangeRow<'a> {
+    /// Unix timestamp of the poll that found the change.
+    time: u64,
+    #[serde(flatten)]
+    change: &'a Change,
+}
+
+fn print_rows<T: Serialize>(format: OutputFormat, rows: &[T]) -> Result<(), FritzerError> {
+    write_rows(&mut io::stdout().lock(), format, rows)
+}
+
+/// Prints the outcome for every device and returns the first error.
+fn print_results<T, R: Serialize>(
+    format: OutputFormat,
+    results: Vec<BulkResult<T>>,
+    row: impl Fn(Ain, Result<T, String>) -> R,
+) -> Result<(), FritzerError> {
+    let mut first_error = None;
+    let mut rows = Vec::new();
+
+    for BulkResult { ain, result } in results {
+        match result {
+            Ok(value) => rows.push(row(ain, Ok(value))),
+            Err(e) => {
+                rows.push(row(ain, Err(e.to_string())));
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    print_rows(format, &rows)?;
+
+    first_error.map_or(Ok(()), Err)
+}
+
+/// The CSV columns of `watch`: the fields of all events, since the header is
+/// only written once.
+const CHANGE_COLUMNS: [&str; 13] = [
+    "time",
+    "ain",
+    "name",
+    "event",
+    "present",
+    "on",
+    "power",
+    "previous",
+    "temperature",
+    "alarm",
+    "button",
+    "button_name",
+    "timestamp",
+];
+
+/// Prints the changes of one poll as a continuation of the previous ones:
+/// JSON is streamed as one object per change, YAML as items of one sequence
+/// and CSV below a single header.
+fn print_changes(
+    format: OutputFormat,
+    changes: &[Change],
+    csv_header: &mut bool,
+) -> Result<(), FritzerError> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let rows: Vec<ChangeRow> = changes
+        .iter()
+        .map(|change| ChangeRow { time, change })
+        .collect();
+
+    match format {
+        OutputFormat::Table => {
+            for change in changes {
+                println!("{}", change);
+            }
+
+            Ok(())
+        }
+        OutputFormat::Csv => write_csv_rows(
+            &mut io::stdout().lock(),
+            &CHANGE_COLUMNS,
+            std::mem::take(csv_header),
+            &rows,
+        ),
+        OutputFormat::Json | OutputFormat::Ndjson => print_rows(OutputFormat::Ndjson, &rows),
+        OutputFormat::Yaml => print_rows(format, &rows),
+    }
+}
+
+/// Polls the devices every `interval` and prints their changes until Ctrl-C.
+/// Failed polls are skipped unless the session cannot be restored.
+async fn watch(
+    fritzbox: &ConnectedFritzbox,
+    format: OutputFormat,
+    interval: Duration,
+    thresholds: Thresholds,
+    targets: &[Target],
+    tags: &target::Tags,
+) -> Result<(), FritzerError> {
+    let mut previous = fritzbox.get_device_infos().await?;
+    let ains = match targets.is_empty() {
+        true => None,
+        false => {
+            let devices: Vec<Device> = previous.iter().map(Device::from).collect();
+
+            Some(target::resolve(targets, &devices, tags)?)
         }
     };
+    let watched = |devices: Vec<DeviceInfo>| match &ains {
+        Some(ains) => devices
+            .into_iter()
+            .filter(|d| ains.contains(&d.ain))
+            .collect(),
+        None => devices,
+    };
 
-    fritzbox
+    previous = watched(previous);
+    info!("Watching {} devices...", previous.len());
+
+    let mut csv_header = true;
+    let mut ticker = tokio::time::interval(interval);
+    // Created once, so that a Ctrl-C during a poll ends the next wait.
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    tokio::pin!(ctrl_c);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => return Ok(()),
+        }
+
+        let current = match fritzbox.get_device_infos().await {
+            Ok(devices) => watched(devices),
+            Err(
+                e @ (FritzerError::Http(_)
+                | FritzerError::Status(_)
+                | FritzerError::Xml(_)
+                | FritzerError::InvalidResponse(_)),
+            ) => {
+                warn!("Skipping poll: {}", e);
+
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        print_changes(
+            format,
+            &watch::diff(&previous, &current, &thresholds),
+            &mut csv_header,
+        )?;
+        previous = current;
+    }
 }
 
-async fn list_devices(devices: &Vec<Device>) {
-    println!("| {0: <2} | {1: <12} | {2: <10} |", "Nr", "AIN", "Name");
-    println!("+----+--------------+------------+");
-    for (i, device) in devices.iter().enumerate() {
-        println!(
-            "| {0: <2} | {1: <12} | {2: <10} |",
-            i, device.ain, device.name
-        );
+/// Fails with [`FritzerError::CheckFailed`] unless all switches are on. A
+/// switch that is not present or unknown to the FRITZ!Box is not on.
+async fn is_on(
+    fritzbox: &ConnectedFritzbox,
+    targets: &[Target],
+    tags: &target::Tags,
+) -> Result<(), FritzerError> {
+    let ains = fritzbox.resolve_targets(targets, tags).await?;
+    let mut off = Vec::new();
+
+    for ain in ains {
+        match fritzbox.get_switch_state(&ain).await {
+            Ok(true) => {}
+            Ok(false) | Err(FritzerError::DeviceNotPresent(_) | FritzerError::InvalidAin(_)) => {
+                off.push(ain.to_string())
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match off.is_empty() {
+        true => Ok(()),
+        false => Err(FritzerError::CheckFailed(format!(
+            "Not on: {}.",
+            off.join(", ")
+        ))),
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    simple_logger::init_with_level(Level::Debug).unwrap();
+/// Fails with [`FritzerError::CheckFailed`] unless all devices are present. An
+/// AIN that is missing from the device list is not present.
+async fn is_present(
+    fritzbox: &ConnectedFritzbox,
+    targets: &[Target],
+    tags: &target::Tags,
+) -> Result<(), FritzerError> {
+    let infos = fritzbox.get_device_infos().await?;
+    let devices: Vec<Device> = infos.iter().map(Device::from).collect();
+    let absent: Vec<String> = target::resolve(targets, &devices, tags)?
+        .into_iter()
+        .filter_map(|ain| match infos.iter().find(|device| device.ain == ain) {
+            Some(device) if device.present => None,
+            Some(device) => Some(format!("{} ({})", device.name, device.ain)),
+            None => Some(ain.to_string()),
+        })
+        .collect();
 
-    let args = Args::parse();
-    let fritzbox =
-        connect_to_fritzbox(&args.url, &args.username, &args.password, &args.sid_path).await;
-    let session_info = fritzbox.session_info.as_ref().unwrap();
+    match absent.is_empty() {
+        true => Ok(()),
+        false => Err(FritzerError::CheckFailed(format!(
+            "Not present: {}.",
+            absent.join(", ")
+        ))),
+    }
+}
+
+fn exit_code(error: &FritzerError) -> u8 {
+    match error {
+        FritzerError::CheckFailed(_) => 1,
+        FritzerError::InvalidValue(_) | FritzerError::Config(_) => 2,
+        FritzerError::NotConnected
+        | FritzerError::LoginFailed
+        | FritzerError::Blocked(_)
+        | FritzerError::Credentials(_) => 3,
+        FritzerError::Http(_) | FritzerError::Status(_) | FritzerError::Tls(_) => 4,
+        FritzerError::Unsupported(_) => 5,
+        FritzerError::InvalidAin(_)
+        | FritzerError::DeviceNotPresent(_)
+        | FritzerError::UnknownTarget(_)
+        | FritzerError::AmbiguousTarget(_, _) => 6,
+        FritzerError::Xml(_) | FritzerError::InvalidResponse(_) | FritzerError::Io(_) => 7,
+    }
+}
 
-    debug!("The SID {:?}", session_info.sid);
+/// With `--dry-run`, requests changing devices are printed to stderr instead
+/// of sent, so that they do not mix with the output of the command.
+fn get_switch_operator(args: &Args, url: &Url) -> Box<dyn SwitchOperator> {
+    if !args.dry_run {
+        return Box::new(FritzboxSwitchOperator);
+    }
 
-    match &args.command {
-        Some(Commands::Switch { list }) => {
-            if *list {
-                debug!("List switches...");
+    let base = url.as_str().trim_end_matches('/').to_string();
+
+    Box::new(RecordingSwitchOperator::new(
+        FritzboxSwitchOperator,
+        move |request| match &request.ain {
+            Some(ain) => eprintln!(
+                "Would send to {}: GET {}{}?{}",
+                ain, base, request.path, request.query
+            ),
+            None => eprintln!("Would send: GET {}{}?{}", base, request.path, request.query),
+        },
+    ))
+}
+
+/// Connects to the FRITZ!Box, re-using a cached session if possible.
+async fn connect(
+    args: &Args,
+    profile: &Profile,
+    url: &Url,
+) -> Result<ConnectedFritzbox, FritzerError> {
+    let builder = get_fritzbox_builder(args, profile, url)?;
+    let username = args.username.clone().or_else(|| profile.username.clone());
+    let credential_source = get_credential_source(args, profile);
+    let sid_cache = get_sid_cache(args, profile);
+    let cache_derived_key = args.cache_derived_key || profile.cache_derived_key;
+
+    connect_to_fritzbox(
+        builder
+            .switch_operator(get_switch_operator(args, url))
+            .build()?,
+        url,
+        &username,
+        &credential_source,
+        &sid_cache,
+        cache_derived_key,
+    )
+    .await
+}
+
+async fn fetch_fingerprint(args: &Args, profile: &Profile, url: &Url) -> Result<(), FritzerError> {
+    let fingerprint = get_fritzbox_builder(args, profile, url)?
+        .fetch_fingerprint()
+        .await?;
 
-                let switches = fritzbox.get_switches().await.unwrap();
+    print_rows(
+        args.output(profile),
+        &[FingerprintRow {
+            fingerprint: fingerprint.to_string(),
+        }],
+    )
+}
+
+/// Runs `command` with the options of `args`, which are the same for every
+/// command of a shell session.
+async fn execute(
+    args: &Args,
+    profile: &Profile,
+    url: &Url,
+    fritzbox: &Arc<ConnectedFritzbox>,
+    command: &Commands,
+) -> Result<(), FritzerError> {
+    match command {
+        Commands::Switch {
+            command: Some(command),
+            ..
+        } => {
+            let (ains, action) = match command {
+                SwitchCommands::On { targets } => (targets, SwitchAction::On),
+                SwitchCommands::Off { targets } => (targets, SwitchAction::Off),
+                SwitchCommands::Toggle { targets } => (targets, SwitchAction::Toggle),
+                SwitchCommands::IsOn { targets } => {
+                    return is_on(fritzbox, targets, &profile.tags).await
+                }
+            };
+            let ains = fritzbox.resolve_targets(ains, &profile.tags).await?;
+
+            debug!("Switch {} switches {:?}...", ains.len(), action);
 
-                list_devices(&switches).await;
+            let results = fritzbox.set_switches(&ains, action).await;
+
+            print_results(args.output(profile), results, |ain, result| SwitchRow {
+                ain,
+                on: result.as_ref().ok().copied(),
+                error: result.err(),
+            })?;
+        }
+        Commands::Switch { list: true, .. } => {
+            debug!("List switches...");
+
+            let switches = fritzbox.get_switches().await?;
+
+            print_rows(args.output(profile), &switches)?;
+        }
+        Commands::Switch { ain, .. } if !ain.is_empty() => {
+            let ains = fritzbox.resolve_targets(ain, &profile.tags).await?;
+
+            debug!("Get switches {:?}...", ains);
+
+            let results = fritzbox.get_switches_by_ain(&ains).await;
+
+            print_results(args.output(profile), results, |ain, result| DeviceRow {
+                ain,
+                name: result.as_ref().ok().map(|device| device.name.clone()),
+                error: result.err(),
+            })?;
+        }
+        Commands::Watch {
+            interval,
+            power_threshold,
+            temperature_threshold,
+            targets,
+        } => {
+            let thresholds = Thresholds {
+                power: *power_threshold,
+                temperature: *temperature_threshold,
+            };
+
+            watch(
+                fritzbox,
+                args.output(profile),
+                Duration::from_secs(*interval),
+                thresholds,
+                targets,
+                &profile.tags,
+            )
+            .await?;
+        }
+        Commands::Thermostat {
+            command: ThermostatCommands::Set { targets, setpoint },
+        } => {
+            let thermostats: Vec<Device> = fritzbox
+                .get_device_infos()
+                .await?
+                .iter()
+                .filter(|device| device.thermostat.is_some())
+                .map(Device::from)
+                .collect();
+            let ains = target::resolve(targets, &thermostats, &profile.tags)?;
+
+            debug!("Set {} thermostats to {}...", ains.len(), setpoint);
+
+            let results = fritzbox.set_thermostats(&ains, *setpoint).await;
+
+            print_results(args.output(profile), results, |ain, result| ThermostatRow {
+                ain,
+                setpoint: result.as_ref().ok().map(|_| setpoint.to_string()),
+                error: result.err(),
+            })?;
+        }
+        Commands::Template {
+            command: TemplateCommands::List,
+        } => {
+            debug!("List templates...");
+
+            print_rows(args.output(profile), &fritzbox.get_templates().await?)?;
+        }
+        Commands::Template {
+            command: TemplateCommands::Apply { templates },
+        } => {
+            let devices: Vec<Device> = fritzbox
+                .get_templates()
+                .await?
+                .into_iter()
+                .map(|template| Device {
+                    ain: template.ain,
+                    name: template.name,
+                })
+                .collect();
+            let ains = target::resolve(templates, &devices, &target::Tags::new())?;
+            let mut results = Vec::new();
+
+            for ain in ains {
+                debug!("Apply template {}...", ain);
+
+                let result = fritzbox.apply_template(&ain).await;
+
+                results.push(BulkResult { ain, result });
             }
+
+            print_results(args.output(profile), results, |ain, result| DeviceRow {
+                name: devices
+                    .iter()
+                    .find(|device| device.ain == ain)
+                    .map(|device| device.name.clone()),
+                ain,
+                error: result.err(),
+            })?;
         }
-        None => {}
+        Commands::Device {
+            command: DeviceCommands::IsPresent { targets },
+        } => is_present(fritzbox, targets, &profile.tags).await?,
+        Commands::Device {
+            command: DeviceCommands::Rename { target, name },
+        } => {
+            let devices: Vec<Device> = fritzbox
+                .get_device_infos()
+                .await?
+                .iter()
+                .map(Device::from)
+                .collect();
+            let ains = target::resolve(std::slice::from_ref(target), &devices, &profile.tags)?;
+            let ain = match ains.as_slice() {
+                [ain] => ain,
+                _ => {
+                    return Err(FritzerError::InvalidValue(format!(
+                        "'{}' matches {} devices. Rename one device at a time.",
+                        target,
+                        ains.len()
+                    )))
+                }
+            };
+
+            debug!("Rename {} to '{}'...", ain, name);
+
+            fritzbox.set_name(ain, name).await?;
+
+            print_rows(
+                args.output(profile),
+                &[DeviceRow {
+                    ain: ain.clone(),
+                    name: Some(name.clone()),
+                    error: None,
+                }],
+            )?;
+        }
+        Commands::Raw {
+            switchcmd,
+            ain,
+            pretty,
+            parameters,
+        } => {
+            debug!("Send {}...", switchcmd);
+
+            let body = fritzbox
+                .raw_command(switchcmd, ain.as_ref(), parameters)
+                .await?;
+            let body = match pretty {
+                true => aha::pretty_xml(&body).unwrap_or(body),
+                false => body,
+            };
+
+            if args.output(profile) == OutputFormat::Table {
+                print!("{}", body);
+
+                if !body.is_empty() && !body.ends_with('\n') {
+                    println!();
+                }
+            } else {
+                print_rows(
+                    args.output(profile),
+                    &[RawRow {
+                        switchcmd,
+                        ain: ain.as_ref(),
+                        body,
+                    }],
+                )?;
+            }
+        }
+        Commands::Tui { .. } if args.dry_run => {
+            return Err(FritzerError::InvalidValue(
+                "--dry-run cannot be used with the dashboard.".to_string(),
+            ));
+        }
+        Commands::Tui { interval } => {
+            tui::run(fritzbox.clone(), Duration::from_secs(*interval)).await?;
+        }
+        Commands::Tls {
+            command: TlsCommands::FetchFingerprint,
+        } => fetch_fingerprint(args, profile, url).await?,
+        // Clap requires an action of `switch`. The shell and batches are
+        // started by `run` and cannot be nested.
+        Commands::Switch { .. } | Commands::Shell | Commands::Batch { .. } => {}
     }
 
     Ok(())
 }
+
+async fn run(args: &Args) -> Result<(), FritzerError> {
+    let profile = get_profile(args);
+    let url = get_url(args, &profile);
+
+    match &args.command {
+        Some(Commands::Tls {
+            command: TlsCommands::FetchFingerprint,
+        }) => fetch_fingerprint(args, &profile, &url).await,
+        Some(Commands::Shell) => shell::run(args, &profile, &url).await,
+        Some(Commands::Batch {
+            file,
+            continue_on_error,
+        }) => batch::run(args, &profile, &url, file.as_deref(), *continue_on_error).await,
+        Some(command) => {
+            let fritzbox = Arc::new(connect(args, &profile, &url).await?);
+
+            execute(args, &profile, &url, &fritzbox, command).await
+        }
+        None => connect(args, &profile, &url).await.map(|_| ()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    logger::init(args.verbose, args.quiet, args.log_format);
+
+    match run(&args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e @ FritzerError::CheckFailed(_)) => {
+            info!("{}", e);
+
+            ExitCode::from(exit_code(&e))
+        }
+        Err(e) => {
+            error!("{}", e);
+
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_trait::async_trait;
+    use libfritzer::transport::{Response, Transport};
+    use url::form_urlencoded;
+
+    /// AIN, name, whether the device is present and the state of its switch.
+    const DEVICES: [(&str, &str, bool, &str); 3] = [
+        ("087610000434", "Kettle", true, "1"),
+        ("087610000435", "Lamp", true, "0"),
+        ("087610000436", "Heater", false, "inval"),
+    ];
+
+    /// Answers like a FRITZ!Box with [`DEVICES`]. Unknown AINs are rejected
+    /// with HTTP 400.
+    struct FakeFritzbox;
+
+    #[async_trait]
+    impl Transport for FakeFritzbox {
+        async fn get(&self, _path: &str, query: Option<&str>) -> Result<Response, FritzerError> {
+            let parameters: Vec<(String, String)> =
+                form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                    .into_owned()
+                    .collect();
+            let parameter = |name: &str| {
+                parameters
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            let device = DEVICES
+                .iter()
+                .find(|device| Some(device.0) == parameter("ain"));
+            let body = match (parameter("switchcmd"), device) {
+                (Some("getdevicelistinfos"), _) => {
+                    let devices: String = DEVICES
+                        .iter()
+                        .map(|(ain, name, present, state)| {
+                            format!(
+                                "<device identifier=\"{}\" functionbitmask=\"35712\" \
+                                 productname=\"FRITZ!DECT 200\"><present>{}</present>\
+                                 <name>{}</name><switch><state>{}</state></switch></device>",
+                                ain,
+                                u8::from(*present),
+                                name,
+                                state.replace("inval", "")
+                            )
+                        })
+                        .collect();
+
+                    format!("<devicelist version=\"1\">{}</devicelist>", devices)
+                }
+                (Some("getswitchlist"), _) => {
+                    let ains: Vec<&str> = DEVICES.iter().map(|device| device.0).collect();
+
+                    ains.join(",")
+                }
+                (Some("getswitchname"), Some(device)) => device.1.to_string(),
+                (Some("getswitchstate"), Some(device)) => device.3.to_string(),
+                _ => {
+                    return Ok(Response {
+                        status: 400,
+                        body: String::new(),
+                    })
+                }
+            };
+
+            Ok(Response {
+                status: 200,
+                body: format!("{}\n", body),
+            })
+        }
+
+        async fn post_form(&self, _path: &str, _body: &str) -> Result<Response, FritzerError> {
+            Ok(Response {
+                status: 200,
+                body:
+                    "<SessionInfo><SID>0123456789abcdef</SID><Challenge>2$10$ab$10$cd</Challenge>\
+                       <BlockTime>0</BlockTime><Users><User>fritzer</User></Users></SessionInfo>"
+                        .to_string(),
+            })
+        }
+    }
+
+    async fn fritzbox() -> ConnectedFritzbox {
+        let switch_operator: Box<dyn SwitchOperator> = Box::new(FritzboxSwitchOperator);
+
+        FritzboxBuilder::new(Url::parse("http://fritz.box").unwrap())
+            .transport(FakeFritzbox)
+            .switch_operator(switch_operator)
+            .build()
+            .unwrap()
+            .connect_with_sid("0123456789abcdef")
+            .await
+            .unwrap()
+    }
+
+    fn targets(targets: &[&str]) -> Vec<Target> {
+        targets
+            .iter()
+            .map(|target| target.parse().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn is_on_should_check_every_switch() {
+        // Arrange
+        let fritzbox = fritzbox().await;
+        let tags = target::Tags::new();
+
+        // Act
+        let on = is_on(&fritzbox, &targets(&["Kettle", "08761 0000434"]), &tags).await;
+        let off = is_on(&fritzbox, &targets(&["Kettle", "Lamp"]), &tags).await;
+        let absent = is_on(&fritzbox, &targets(&["Heater"]), &tags).await;
+        let unknown_ain = is_on(&fritzbox, &targets(&["08761 0000999"]), &tags).await;
+        let unknown_name = is_on(&fritzbox, &targets(&["Garage"]), &tags).await;
+
+        // Assert
+        assert!(on.is_ok());
+        assert!(matches!(off, Err(FritzerError::CheckFailed(_))));
+        assert!(matches!(absent, Err(FritzerError::CheckFailed(_))));
+        assert!(matches!(unknown_ain, Err(FritzerError::CheckFailed(_))));
+        assert!(matches!(unknown_name, Err(FritzerError::UnknownTarget(_))));
+    }
+
+    #[tokio::test]
+    async fn is_present_should_check_every_device() {
+        // Arrange
+        let fritzbox = fritzbox().await;
+        let tags = target::Tags::new();
+
+        // Act
+        let present = is_present(&fritzbox, &targets(&["Kettle", "087610000435"]), &tags).await;
+        let absent = is_present(&fritzbox, &targets(&["Kettle", "Heater"]), &tags).await;
+        let unknown_ain = is_present(&fritzbox, &targets(&["08761 0000999"]), &tags).await;
+        let unknown_name = is_present(&fritzbox, &targets(&["Garage"]), &tags).await;
+
+        // Assert
+        assert!(present.is_ok());
+        assert!(matches!(absent, Err(FritzerError::CheckFailed(_))));
+        assert!(matches!(unknown_ain, Err(FritzerError::CheckFailed(_))));
+        assert!(matches!(unknown_name, Err(FritzerError::UnknownTarget(_))));
+        assert_eq!(1, exit_code(&absent.unwrap_err()));
+        assert_eq!(6, exit_code(&unknown_name.unwrap_err()));
+    }
+}
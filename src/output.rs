@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+use crate::error::FritzerError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Aligned columns for humans. Without rows, a short note.
+    #[default]
+    Table,
+    /// One JSON array
+    Json,
+    /// Comma-separated values with a header line
+    Csv,
+    /// One YAML sequence
+    Yaml,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// Writes `rows` in the given format. The fields of the rows become the
+/// columns of tables and CSV. Nested values are written as JSON there.
+pub fn write_rows<W: Write, T: Serialize>(
+    writer: &mut W,
+    format: OutputFormat,
+    rows: &[T],
+) -> Result<(), FritzerError> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, rows).map_err(io::Error::from)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut *writer, row).map_err(io::Error::from)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Yaml => {
+            serde_yaml::to_writer(&mut *writer, rows).map_err(io::Error::other)?;
+        }
+        OutputFormat::Csv => {
+            let (columns, cells) = tabulate(rows)?;
+            let mut csv = csv::Writer::from_writer(&mut *writer);
+
+            if !columns.is_empty() {
+                csv.write_record(&columns).map_err(io::Error::from)?;
+            }
+
+            for row in cells {
+                csv.write_record(&row).map_err(io::Error::from)?;
+            }
+
+            csv.flush()?;
+        }
+        OutputFormat::Table => {
+            let (columns, cells) = tabulate(rows)?;
+
+            write_table(writer, &columns, &cells)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `rows` as CSV with fixed `columns`, so that rows of different
+/// shapes can be streamed below one header. Fields missing from `columns`
+/// are dropped.
+pub fn write_csv_rows<W: Write, T: Serialize>(
+    writer: &mut W,
+    columns: &[&str],
+    header: bool,
+    rows: &[T],
+) -> Result<(), FritzerError> {
+    let mut csv = csv::Writer::from_writer(&mut *writer);
+
+    if header {
+        csv.write_record(columns).map_err(io::Error::from)?;
+    }
+
+    for object in objects(rows)? {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|column| cell(object.get(*column)))
+            .collect();
+
+        csv.write_record(&cells).map_err(io::Error::from)?;
+    }
+
+    csv.flush()?;
+
+    Ok(())
+}
+
+fn objects<T: Serialize>(rows: &[T]) -> Result<Vec<Map<String, Value>>, FritzerError> {
+    rows.iter()
+        .map(
+            |row| match serde_json::to_value(row).map_err(io::Error::from)? {
+                Value::Object(object) => Ok(object),
+                value => Ok(Map::from_iter([("value".to_string(), value)])),
+            },
+        )
+        .collect()
+}
+
+/// Splits `rows` into column names, in the order of first appearance, and
+/// the cells of each row.
+fn tabulate<T: Serialize>(rows: &[T]) -> Result<(Vec<String>, Vec<Vec<String>>), FritzerError> {
+    let objects = objects(rows)?;
+    let mut columns: Vec<String> = Vec::new();
+
+    for object in &objects {
+        for key in object.keys() {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+
+    let cells = objects
+        .iter()
+        .map(|object| {
+            columns
+                .iter()
+                .map(|column| cell(object.get(column)))
+                .collect()
+        })
+        .collect();
+
+    Ok((columns, cells))
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn write_table<W: Write>(
+    writer: &mut W,
+    columns: &[String],
+    cells: &[Vec<String>],
+) -> Result<(), io::Error> {
+    if columns.is_empty() {
+        return writeln!(writer, "No entries.");
+    }
+
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain([column.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let line = |writer: &mut W, values: &[String]| {
+        let padded: Vec<String> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{: <1$}", value, width))
+            .collect();
+
+        writeln!(writer, "| {} |", padded.join(" | "))
+    };
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+
+    line(writer, columns)?;
+    writeln!(writer, "+{}+", separator.join("+"))?;
+
+    for row in cells {
+        line(writer, row)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        ain: String,
+        name: String,
+        on: Option<bool>,
+    }
+
+    fn rows() -> Vec<Row> {
+        vec![
+            Row {
+                ain: "087610000434".to_string(),
+                name: "Kettle in the kitchen, \"left\"".to_string(),
+                on: Some(true),
+            },
+            Row {
+                ain: "087610000435".to_string(),
+                name: "Desk".to_string(),
+                on: None,
+            },
+        ]
+    }
+
+    fn write(format: OutputFormat) -> String {
+        let mut output = Vec::new();
+
+        write_rows(&mut output, format, &rows()).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn write_rows_should_not_truncate_table_cells() {
+        // Arrange
+        let format = OutputFormat::Table;
+
+        // Act
+        let output = write(format);
+
+        // Assert
+        assert_eq!(
+            "| ain          | name                          | on   |\n\
+             +--------------+-------------------------------+------+\n\
+             | 087610000434 | Kettle in the kitchen, \"left\" | true |\n\
+             | 087610000435 | Desk                          |      |\n",
+            output
+        );
+    }
+
+    #[test]
+    fn write_rows_should_note_empty_table() {
+        // Arrange
+        let mut output = Vec::new();
+
+        // Act
+        write_rows::<_, Row>(&mut output, OutputFormat::Table, &[]).unwrap();
+
+        // Assert
+        assert_eq!("No entries.\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn write_rows_should_quote_csv_cells() {
+        // Arrange
+        let format = OutputFormat::Csv;
+
+        // Act
+        let output = write(format);
+
+        // Assert
+        assert_eq!(
+            "ain,name,on\n\
+             087610000434,\"Kettle in the kitchen, \"\"left\"\"\",true\n\
+             087610000435,Desk,\n",
+            output
+        );
+    }
+
+    #[test]
+    fn write_csv_rows_should_write_fixed_columns() {
+        // Arrange
+        let mut output = Vec::new();
+
+        // Act
+        write_csv_rows(&mut output, &["name", "ain", "power"], true, &rows()[1..]).unwrap();
+        write_csv_rows(&mut output, &["name", "ain", "power"], false, &rows()[..1]).unwrap();
+
+        // Assert
+        assert_eq!(
+            "name,ain,power\n\
+             Desk,087610000435,\n\
+             \"Kettle in the kitchen, \"\"left\"\"\",087610000434,\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn write_rows_should_write_one_json_object_per_line() {
+        // Arrange
+        let format = OutputFormat::Ndjson;
+
+        // Act
+        let output = write(format);
+
+        // Assert
+        assert_eq!(
+            "{\"ain\":\"087610000434\",\"name\":\"Kettle in the kitchen, \\\"left\\\"\",\"on\":true}\n\
+             {\"ain\":\"087610000435\",\"name\":\"Desk\",\"on\":null}\n",
+            output
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use log::warn;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::future::Future;
+use std::time::Duration;
+
+use crate::error::FritzerError;
+use crate::transport::Response;
+
+/// How often and how long to wait before a failed request is sent again.
+///
+/// Requests are only repeated if repeating them is safe, see [`Repetition`].
+/// Other statuses, e.g. 403 for an expired SID, are never repeated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of attempts including the first one. 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub const NONE: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    pub fn with_retries(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: retries.saturating_add(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Exponential backoff before the given retry (starting at 1), of which
+    /// a random half is skipped so that clients do not retry in lockstep.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(16);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        let half = backoff / 2;
+
+        half + half.mul_f64(random_fraction())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Which failures a request is repeated after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Repetition {
+    /// Only if the connection could not be established. The FRITZ!Box did
+    /// not receive the request then.
+    Unsent,
+    /// Also while a DECT device is busy (`txbusy`), which the FRITZ!Box
+    /// answers with HTTP 500 or an empty body with a 2xx status. For
+    /// commands that set a state, which a repetition sets again.
+    Busy,
+    /// Also on timeouts and any 5xx status. For reading requests.
+    Idempotent,
+}
+
+fn random_fraction() -> f64 {
+    let mut bytes = [0; 4];
+
+    match SystemRandom::new().fill(&mut bytes) {
+        Ok(()) => f64::from(u32::from_le_bytes(bytes)) / f64::from(u32::MAX),
+        Err(_) => 0.5,
+    }
+}
+
+fn should_retry(
+    result: &Result<Response, FritzerError>,
+    repetition: Repetition,
+    empty_response: bool,
+) -> bool {
+    let idempotent = repetition == Repetition::Idempotent;
+    let busy = repetition != Repetition::Unsent;
+
+    match result {
+        Ok(response) if response.status == 500 => busy,
+        Ok(response) if (500..600).contains(&response.status) => idempotent,
+        Ok(response) if response.is_success() => {
+            response.body.is_empty() && busy && !empty_response
+        }
+        Ok(_) => false,
+        Err(FritzerError::Http(e)) if e.is_connect() => true,
+        Err(FritzerError::Http(e)) => (e.is_timeout() || e.is_request()) && idempotent,
+        Err(_) => false,
+    }
+}
+
+fn describe(result: &Result<Response, FritzerError>) -> String {
+    match result {
+        Ok(response) if response.body.is_empty() => {
+            format!("HTTP {} with empty body", response.status)
+        }
+        Ok(response) => format!("HTTP {}", response.status),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Sends a request via `request` and repeats it according to `policy`.
+/// `empty_response` tells whether an empty body is a valid answer.
+pub(crate) async fn retry<F, Fut>(
+    policy: &RetryPolicy,
+    description: &str,
+    repetition: Repetition,
+    empty_response: bool,
+    request: F,
+) -> Result<Response, FritzerError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Response, FritzerError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        let result = request().await;
+
+        if attempt >= policy.max_attempts || !should_retry(&result, repetition, empty_response) {
+            return result;
+        }
+
+        let backoff = policy.backoff(attempt);
+
+        warn!(
+            "{} failed ({}). Retry {}/{} in {} ms...",
+            description,
+            describe(&result),
+            attempt,
+            policy.max_attempts - 1,
+            backoff.as_millis()
+        );
+
+        tokio::time::sleep(backoff).await;
+
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn response(status: u16, body: &str) -> Result<Response, FritzerError> {
+        Ok(Response {
+            status,
+            body: body.to_string(),
+        })
+    }
+
+    #[test]
+    fn retry_policy_backoff_should_grow_up_to_max_backoff() {
+        // Arrange
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+
+        // Act
+        let first = policy.backoff(1);
+        let third = policy.backoff(3);
+        let last = policy.backoff(9);
+
+        // Assert
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        assert!(last >= Duration::from_millis(500) && last <= Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn retry_should_repeat_idempotent_request_on_empty_body() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "getswitchlist",
+            Repetition::Idempotent,
+            false,
+            || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => response(200, ""),
+                    _ => response(200, "087610000434\n"),
+                }
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!("087610000434\n", result.unwrap().body);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_not_repeat_non_idempotent_request_on_empty_body() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "setswitchtoggle",
+            Repetition::Unsent,
+            false,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                response(200, "")
+            },
+        )
+        .await;
+
+        // Assert
+        assert!(result.is_ok());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_give_up_after_max_attempts_on_busy_device() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "setswitchon",
+            Repetition::Busy,
+            false,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                response(500, "")
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(500, result.unwrap().status);
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_repeat_setting_request_only_while_device_is_busy() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "setswitchon",
+            Repetition::Busy,
+            false,
+            || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => response(200, ""),
+                    1 => response(500, ""),
+                    _ => response(502, ""),
+                }
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(502, result.unwrap().status);
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_not_repeat_setting_request_on_other_server_errors() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "setswitchoff",
+            Repetition::Busy,
+            false,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                response(503, "")
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(503, result.unwrap().status);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_not_repeat_non_idempotent_request_on_busy_device() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "setswitchtoggle",
+            Repetition::Unsent,
+            false,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                response(503, "")
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(503, result.unwrap().status);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_not_repeat_client_errors_with_empty_body() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(
+            &policy(),
+            "getswitchlist",
+            Repetition::Idempotent,
+            false,
+            || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => response(403, ""),
+                    _ => response(400, ""),
+                }
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(403, result.unwrap().status);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retry_should_accept_empty_response_of_idempotent_request() {
+        // Arrange
+        let calls = AtomicUsize::new(0);
+
+        // Act
+        let result = retry(&policy(), "sethkrtsoll", Repetition::Busy, true, || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => response(500, ""),
+                _ => response(200, ""),
+            }
+        })
+        .await;
+
+        // Assert
+        assert!(result.unwrap().body.is_empty());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,476 @@
+use log::{debug, info};
+use ring::{digest, pbkdf2};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::num::NonZeroU32;
+use std::sync::{Arc, RwLock};
+use zeroize::Zeroizing;
+
+use crate::command::Login;
+use crate::connection::SessionInfo;
+use crate::credentials::CredentialSource;
+use crate::error::FritzerError;
+use crate::transport::Transport;
+
+pub static INVALID_SESSION: &str = "0000000000000000";
+const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
+type Credential = [u8; CREDENTIAL_LEN];
+type SessionHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+/// The first PBKDF2 stage of the challenge-response login.
+///
+/// It only depends on the password and the static `iter1`/`salt1` of a
+/// FRITZ!Box user, so it can be reused for every further login of that user
+/// and only the cheap second stage has to be computed per challenge. Keep it as
+/// secret as the password, since it is sufficient to log in.
+#[derive(Clone)]
+pub struct DerivedKey {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    hash: Zeroizing<Credential>,
+}
+
+impl DerivedKey {
+    pub fn derive(password: &str, iterations: NonZeroU32, salt: &[u8]) -> DerivedKey {
+        let mut hash = Zeroizing::new([0u8; CREDENTIAL_LEN]);
+
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            salt,
+            password.as_bytes(),
+            hash.as_mut(),
+        );
+
+        DerivedKey {
+            iterations,
+            salt: salt.to_vec(),
+            hash,
+        }
+    }
+
+    /// Encodes the key as `<iterations>$<salt>$<hash>`.
+    pub fn encode(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}${}${}",
+            self.iterations,
+            hex::encode(&self.salt),
+            hex::encode(self.hash.as_ref())
+        ))
+    }
+
+    pub fn decode(encoded: &str) -> Option<DerivedKey> {
+        let parts: Vec<&str> = encoded.trim().split('$').collect();
+
+        if parts.len() != 3 {
+            return None;
+        }
+
+        let mut hash = Zeroizing::new([0u8; CREDENTIAL_LEN]);
+        hex::decode_to_slice(parts[2], hash.as_mut()).ok()?;
+
+        Some(DerivedKey {
+            iterations: parse_iterations(parts[0])?,
+            salt: hex::decode(parts[1]).ok()?,
+            hash,
+        })
+    }
+
+    fn matches(&self, challenge: &Challenge) -> bool {
+        self.iterations == challenge.iter1 && self.salt == challenge.salt1
+    }
+}
+
+impl fmt::Debug for DerivedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DerivedKey")
+            .field("iterations", &self.iterations)
+            .field("salt", &hex::encode(&self.salt))
+            .finish_non_exhaustive()
+    }
+}
+
+/// A PBKDF2 challenge `2$<iter1>$<salt1>$<iter2>$<salt2>`.
+struct Challenge {
+    iter1: NonZeroU32,
+    salt1: Vec<u8>,
+    iter2: NonZeroU32,
+    salt2: Vec<u8>,
+}
+
+impl Challenge {
+    fn parse(challenge: &str) -> Result<Challenge, FritzerError> {
+        let invalid_challenge =
+            || FritzerError::InvalidResponse(format!("Unsupported challenge '{}'.", challenge));
+        let challenges: Vec<&str> = challenge.split('$').collect();
+
+        if challenges.len() != 5 || challenges[0] != "2" {
+            return Err(invalid_challenge());
+        }
+
+        Ok(Challenge {
+            iter1: parse_iterations(challenges[1]).ok_or_else(invalid_challenge)?,
+            salt1: hex::decode(challenges[2]).map_err(|_| invalid_challenge())?,
+            iter2: parse_iterations(challenges[3]).ok_or_else(invalid_challenge)?,
+            salt2: hex::decode(challenges[4]).map_err(|_| invalid_challenge())?,
+        })
+    }
+
+    fn derive_key(&self, password: &str) -> DerivedKey {
+        DerivedKey::derive(password, self.iter1, &self.salt1)
+    }
+
+    fn response(&self, key: &DerivedKey) -> String {
+        let mut hash2: Credential = [0u8; CREDENTIAL_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            self.iter2,
+            &self.salt2,
+            key.hash.as_ref(),
+            &mut hash2,
+        );
+
+        format!("{}${}", hex::encode(&self.salt2), hex::encode(hash2))
+    }
+}
+
+enum Secret {
+    Password(Zeroizing<String>),
+    Key(DerivedKey),
+}
+
+struct Credentials {
+    username: String,
+    key: DerivedKey,
+}
+
+/// Keeps track of the SID of a FRITZ!Box session and logs in again with the
+/// stored credentials once the FRITZ!Box rejects the SID.
+pub struct SessionManager<L: Login + ?Sized> {
+    transport: Arc<dyn Transport>,
+    login: Arc<L>,
+    session_info: RwLock<Option<SessionInfo>>,
+    credentials: RwLock<Option<Credentials>>,
+    /// Where to read the password from if there are no credentials yet.
+    credential_source: RwLock<Option<(String, CredentialSource)>>,
+    on_renewal: RwLock<Option<SessionHandler>>,
+    on_use: RwLock<Option<SessionHandler>>,
+    reauthentication: tokio::sync::Mutex<()>,
+}
+
+impl<L: Login + ?Sized> SessionManager<L> {
+    pub fn new(transport: Arc<dyn Transport>, login: Arc<L>) -> SessionManager<L> {
+        SessionManager {
+            transport,
+            login,
+            session_info: RwLock::new(None),
+            credentials: RwLock::new(None),
+            credential_source: RwLock::new(None),
+            on_renewal: RwLock::new(None),
+            on_use: RwLock::new(None),
+            reauthentication: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+
+    pub fn session_info(&self) -> Option<SessionInfo> {
+        self.session_info.read().unwrap().clone()
+    }
+
+    pub fn sid(&self) -> Option<String> {
+        match self.session_info.read().unwrap().as_ref() {
+            Some(s) if !s.sid.eq(INVALID_SESSION) => Some(s.sid.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.sid().is_some()
+    }
+
+    pub async fn update_session_info(&self) -> Result<(), FritzerError> {
+        let session_info = self.login.get_session_info(self.transport.as_ref()).await?;
+
+        self.set_session_info(session_info);
+
+        Ok(())
+    }
+
+    pub async fn connect_with_sid(&self, sid: &str) -> Result<(), FritzerError> {
+        let session_info = self
+            .login
+            .connect_with_sid(self.transport.as_ref(), sid)
+            .await?;
+
+        self.set_session_info(session_info);
+
+        match self.is_connected() {
+            true => Ok(()),
+            false => Err(FritzerError::NotConnected),
+        }
+    }
+
+    pub async fn connect_with_credentials(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<(), FritzerError> {
+        let secret = Secret::Password(Zeroizing::new(password.to_string()));
+
+        self.authenticate(username, secret).await
+    }
+
+    /// Logs in with a key from [`SessionManager::derived_key`], which saves
+    /// the expensive first PBKDF2 stage.
+    pub async fn connect_with_derived_key(
+        &self,
+        username: &str,
+        key: DerivedKey,
+    ) -> Result<(), FritzerError> {
+        self.authenticate(username, Secret::Key(key)).await
+    }
+
+    pub fn derived_key(&self) -> Option<DerivedKey> {
+        self.credentials
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|c| c.key.clone())
+    }
+
+    /// Lets the session manager log in as `username` with the password of
+    /// `source` once the SID expires and it holds no credentials from an
+    /// earlier login, or the FRITZ!Box rejects them. The password is only
+    /// read when needed, so a session connected with a cached SID does not
+    /// ask for it up front.
+    pub fn set_credential_source(&self, username: &str, source: CredentialSource) {
+        *self.credential_source.write().unwrap() = Some((username.to_string(), source));
+    }
+
+    /// Calls `on_renewal` with the new SID whenever the session manager had to
+    /// log in again, e.g. to update a cache of SIDs.
+    pub fn on_renewal(&self, on_renewal: impl Fn(&str) + Send + Sync + 'static) {
+        *self.on_renewal.write().unwrap() = Some(Box::new(on_renewal));
+    }
+
+    /// Calls `on_use` with the SID after every successful operation, e.g. to
+    /// keep a cache of SIDs fresh, since every request extends the session.
+    pub fn on_use(&self, on_use: impl Fn(&str) + Send + Sync + 'static) {
+        *self.on_use.write().unwrap() = Some(Box::new(on_use));
+    }
+
+    /// Runs `operation` with the current SID. If the FRITZ!Box rejects the
+    /// SID, the session manager logs in again and retries `operation` once.
+    pub async fn with_session<T, F, Fut>(&self, operation: F) -> Result<T, FritzerError>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T, FritzerError>>,
+    {
+        let sid = match self.sid() {
+            Some(sid) => sid,
+            None => self.reauthenticate(INVALID_SESSION).await?,
+        };
+
+        let (sid, result) = match operation(sid.clone()).await {
+            Err(FritzerError::NotConnected) => {
+                info!("Session expired. Re-authenticate...");
+
+                let sid = self.reauthenticate(&sid).await?;
+                let result = operation(sid.clone()).await;
+
+                (sid, result)
+            }
+            result => (sid, result),
+        };
+
+        if result.is_ok() {
+            if let Some(on_use) = self.on_use.read().unwrap().as_ref() {
+                on_use(&sid);
+            }
+        }
+
+        result
+    }
+
+    async fn reauthenticate(&self, expired_sid: &str) -> Result<String, FritzerError> {
+        let _guard = self.reauthentication.lock().await;
+
+        if let Some(sid) = self.sid() {
+            if !sid.eq(expired_sid) {
+                debug!("Session has already been renewed.");
+
+                return Ok(sid);
+            }
+        }
+
+        let credentials = self
+            .credentials
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|c| (c.username.clone(), c.key.clone()));
+        let credential_source = self.credential_source.read().unwrap().clone();
+
+        match (credentials, credential_source) {
+            (Some((username, key)), credential_source) => {
+                match (
+                    self.authenticate(&username, Secret::Key(key)).await,
+                    credential_source,
+                ) {
+                    // The salt or the password changed since the key was derived.
+                    (Err(FritzerError::LoginFailed), Some(credential_source)) => {
+                        debug!("Derived key rejected. Logging in with the password...");
+
+                        self.authenticate_with_source(credential_source).await?
+                    }
+                    (result, _) => result?,
+                }
+            }
+            (None, Some(credential_source)) => {
+                self.authenticate_with_source(credential_source).await?
+            }
+            (None, None) => return Err(FritzerError::NotConnected),
+        }
+
+        let sid = self.sid().ok_or(FritzerError::NotConnected)?;
+
+        if let Some(on_renewal) = self.on_renewal.read().unwrap().as_ref() {
+            on_renewal(&sid);
+        }
+
+        Ok(sid)
+    }
+
+    /// Reads the password of `source` and logs in as `username`, replacing the
+    /// stored key.
+    async fn authenticate_with_source(
+        &self,
+        (username, source): (String, CredentialSource),
+    ) -> Result<(), FritzerError> {
+        // Prompts and password commands block.
+        let password = tokio::task::spawn_blocking(move || source.read_password())
+            .await
+            .map_err(|e| FritzerError::Io(io::Error::other(e)))??;
+
+        self.authenticate(&username, Secret::Password(password))
+            .await
+    }
+
+    async fn authenticate(&self, username: &str, secret: Secret) -> Result<(), FritzerError> {
+        self.update_session_info().await?;
+
+        let challenge = match self.session_info() {
+            Some(s) => Challenge::parse(&s.challenge)?,
+            None => return Err(FritzerError::NotConnected),
+        };
+
+        if let Secret::Key(key) = &secret {
+            if !key.matches(&challenge) {
+                debug!("Derived key does not match the challenge.");
+
+                return Err(FritzerError::LoginFailed);
+            }
+        }
+
+        // PBKDF2 is CPU-bound, so keep it off the async runtime.
+        let (key, response) = tokio::task::spawn_blocking(move || {
+            let key = match secret {
+                Secret::Password(password) => challenge.derive_key(&password),
+                Secret::Key(key) => key,
+            };
+            let response = challenge.response(&key);
+
+            (key, response)
+        })
+        .await
+        .map_err(|e| FritzerError::Io(io::Error::other(e)))?;
+
+        let session_info = self
+            .login
+            .connect_with_credentials(self.transport.as_ref(), username, &response)
+            .await?;
+        let block_time = session_info.as_ref().map_or(0, |s| s.block_time);
+
+        self.set_session_info(session_info);
+
+        match self.is_connected() {
+            true => {
+                *self.credentials.write().unwrap() = Some(Credentials {
+                    username: username.to_string(),
+                    key,
+                });
+
+                Ok(())
+            }
+            false if block_time > 0 => Err(FritzerError::Blocked(block_time)),
+            false => Err(FritzerError::LoginFailed),
+        }
+    }
+
+    fn set_session_info(&self, session_info: Option<SessionInfo>) {
+        *self.session_info.write().unwrap() = session_info;
+    }
+}
+
+pub fn get_challenge_response(challenge: &str, password: &str) -> Result<String, FritzerError> {
+    let challenge = Challenge::parse(challenge)?;
+
+    Ok(challenge.response(&challenge.derive_key(password)))
+}
+
+fn parse_iterations(iterations: &str) -> Option<NonZeroU32> {
+    NonZeroU32::new(iterations.parse::<u32>().ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_challenge_response_should_return_valid_response() {
+        // Arrange
+        let challenge =
+            "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0";
+        let password = "my$uper$trongPa$$w0rd4U";
+
+        // Act
+        let response = get_challenge_response(challenge, password).unwrap();
+
+        // Assert
+        assert_eq!("d19cee81917f97da37430f45b8352db0$506cf2017a1f3ff399bd66d750979ebdb0cc22fbdaa134acf2ad26c71df6c20f", response);
+    }
+
+    #[test]
+    fn get_challenge_response_should_fail_on_md5_challenge() {
+        // Arrange
+        let challenge = "1234567z";
+
+        // Act
+        let result = get_challenge_response(challenge, "password");
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn challenge_response_should_accept_decoded_derived_key() {
+        // Arrange
+        let challenge = Challenge::parse(
+            "2$60000$c5b7ff41801c5f877d307bbdc93188ef$6000$d19cee81917f97da37430f45b8352db0",
+        )
+        .unwrap();
+        let key = challenge.derive_key("my$uper$trongPa$$w0rd4U");
+
+        // Act
+        let key = DerivedKey::decode(&key.encode()).unwrap();
+        let response = challenge.response(&key);
+
+        // Assert
+        assert!(key.matches(&challenge));
+        assert_eq!("d19cee81917f97da37430f45b8352db0$506cf2017a1f3ff399bd66d750979ebdb0cc22fbdaa134acf2ad26c71df6c20f", response);
+    }
+}
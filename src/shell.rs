@@ -0,0 +1,357 @@
+use clap::{Command, CommandFactory, Parser, Subcommand};
+use libfritzer::{config::Profile, error::FritzerError};
+use log::{error, warn};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use std::{env, fs, path::PathBuf, sync::Arc};
+use url::Url;
+
+use crate::{connect, execute, Args, Commands, ConnectedFritzbox};
+
+const PROMPT: &str = "fritzer> ";
+
+/// A line of the shell or of a batch: any command of fritzer without the
+/// global options, which are fixed for the whole session.
+#[derive(Parser, Debug)]
+#[command(no_binary_name = true, disable_version_flag = true)]
+pub struct Line {
+    #[command(subcommand)]
+    pub command: LineCommands,
+}
+
+impl Line {
+    /// Splits `line` into words like a POSIX shell and parses them. Errors
+    /// are rendered for the terminal.
+    pub fn parse(line: &str) -> Result<Line, String> {
+        shell_words::split(line)
+            .map_err(|e| e.to_string())
+            .and_then(|words| Line::try_parse_from(words).map_err(|e| e.render().to_string()))
+            .map_err(|message| message.trim_end().to_string())
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LineCommands {
+    #[command(flatten)]
+    Command(Commands),
+    /// Leaves the shell
+    #[command(alias = "quit")]
+    Exit,
+}
+
+/// Completes subcommands and options from the command-line definition and
+/// targets from the device names and tags.
+struct LineHelper {
+    command: Command,
+    targets: Vec<String>,
+}
+
+impl LineHelper {
+    fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let start = word_start(line);
+        let words = shell_words::split(&line[..start]).unwrap_or_default();
+        let word = line[start..].trim_start_matches(['"', '\'']).to_lowercase();
+        let mut command = &self.command;
+
+        for word in &words {
+            if let Some(subcommand) = command.find_subcommand(word) {
+                command = subcommand;
+            }
+        }
+
+        let takes_targets = command
+            .get_positionals()
+            .chain(command.get_opts())
+            .any(|arg| {
+                arg.get_value_names()
+                    .is_some_and(|n| n.iter().any(|n| n == "TARGET"))
+            });
+        let candidates: Vec<String> = if word.starts_with('-') {
+            command
+                .get_opts()
+                .chain(command.get_arguments().filter(|a| !a.is_positional()))
+                .filter_map(|arg| arg.get_long())
+                .map(|long| format!("--{}", long))
+                .collect()
+        } else if command.has_subcommands() {
+            command
+                .get_subcommands()
+                .map(|subcommand| subcommand.get_name().to_string())
+                .collect()
+        } else if takes_targets {
+            self.targets
+                .iter()
+                .map(|target| shell_words::quote(target).into_owned())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| {
+                candidate
+                    .trim_start_matches(['"', '\''])
+                    .to_lowercase()
+                    .starts_with(&word)
+            })
+            .collect();
+
+        matches.sort();
+        matches.dedup();
+
+        (start, matches)
+    }
+}
+
+/// The byte index at which the word under the cursor starts, considering
+/// quotes.
+fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, c) if c.is_whitespace() => start = i + c.len_utf8(),
+            _ => {}
+        }
+    }
+
+    start
+}
+
+impl Completer for LineHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, matches) = LineHelper::complete(self, &line[..pos]);
+        let pairs = matches
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for LineHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LineHelper {}
+
+impl Validator for LineHelper {}
+
+impl Helper for LineHelper {}
+
+/// `$XDG_STATE_HOME/fritzer/history`, or `~/.local/state/fritzer/history`.
+fn history_path() -> Option<PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+
+    Some(state_home.join("fritzer").join("history"))
+}
+
+/// A session shared by several commands. If the FRITZ!Box ends the session,
+/// [`ConnectedFritzbox`] logs in again and repeats only the rejected request,
+/// so that no command runs twice.
+pub struct Session<'a> {
+    args: &'a Args,
+    profile: &'a Profile,
+    url: &'a Url,
+    fritzbox: Arc<ConnectedFritzbox>,
+}
+
+impl<'a> Session<'a> {
+    pub async fn connect(
+        args: &'a Args,
+        profile: &'a Profile,
+        url: &'a Url,
+    ) -> Result<Session<'a>, FritzerError> {
+        Ok(Session {
+            args,
+            profile,
+            url,
+            fritzbox: Arc::new(connect(args, profile, url).await?),
+        })
+    }
+
+    pub fn fritzbox(&self) -> &ConnectedFritzbox {
+        &self.fritzbox
+    }
+
+    pub async fn execute(&self, command: &Commands) -> Result<(), FritzerError> {
+        execute(self.args, self.profile, self.url, &self.fritzbox, command).await
+    }
+}
+
+/// Reads commands until `exit` or end of input. All commands share one
+/// [`Session`].
+pub async fn run(args: &Args, profile: &Profile, url: &Url) -> Result<(), FritzerError> {
+    let session = Session::connect(args, profile, url).await?;
+    let mut targets = vec!["all".to_string()];
+
+    targets.extend(profile.tags.keys().map(|tag| format!("tag:{}", tag)));
+
+    match session.fritzbox().get_switches().await {
+        Ok(devices) => targets.extend(devices.into_iter().map(|d| d.name)),
+        Err(e) => warn!("Unable to read the device names for completion: {}", e),
+    }
+
+    let mut editor: Editor<LineHelper, DefaultHistory> =
+        Editor::new().map_err(std::io::Error::other)?;
+    let history = history_path();
+
+    editor.set_helper(Some(LineHelper {
+        command: Line::command(),
+        targets,
+    }));
+
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match tokio::task::block_in_place(|| editor.readline(PROMPT)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e).into()),
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let _ = editor.add_history_entry(line.as_str());
+
+        let command = match Line::parse(&line) {
+            Ok(Line {
+                command: LineCommands::Exit,
+            }) => break,
+            Ok(Line {
+                command: LineCommands::Command(Commands::Shell),
+            }) => {
+                eprintln!("Already in the shell.");
+                continue;
+            }
+            Ok(Line {
+                command: LineCommands::Command(Commands::Batch { .. }),
+            }) => {
+                eprintln!("Batches cannot be run from the shell.");
+                continue;
+            }
+            Ok(Line {
+                command: LineCommands::Command(command),
+            }) => command,
+            Err(message) => {
+                eprintln!("{}", message);
+                continue;
+            }
+        };
+
+        if let Err(e) = session.execute(&command).await {
+            error!("{}", e);
+        }
+    }
+
+    if let Some(path) = &history {
+        let saved = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| editor.save_history(path).map_err(std::io::Error::other));
+
+        if let Err(e) = saved {
+            warn!("Unable to save the shell history: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::error::ErrorKind;
+
+    fn helper() -> LineHelper {
+        LineHelper {
+            command: Line::command(),
+            targets: vec!["all".to_string(), "Desk lamp, left".to_string()],
+        }
+    }
+
+    #[test]
+    fn complete_should_complete_subcommands() {
+        // Arrange
+        let line = "switch t";
+
+        // Act
+        let (start, matches) = helper().complete(line);
+
+        // Assert
+        assert_eq!(7, start);
+        assert_eq!(vec!["toggle"], matches);
+    }
+
+    #[test]
+    fn complete_should_quote_device_names() {
+        // Arrange
+        let line = "switch on 'desk";
+
+        // Act
+        let (start, matches) = helper().complete(line);
+
+        // Assert
+        assert_eq!(10, start);
+        assert_eq!(vec!["'Desk lamp, left'"], matches);
+    }
+
+    #[test]
+    fn line_should_reject_global_options() {
+        // Arrange
+        let words = ["--url", "http://fritz.box", "switch", "-l"];
+
+        // Act
+        let result = Line::try_parse_from(words);
+
+        // Assert
+        assert_eq!(ErrorKind::UnknownArgument, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn line_should_require_action_of_switch() {
+        // Arrange
+        let words = ["switch"];
+
+        // Act
+        let result = Line::try_parse_from(words);
+
+        // Assert
+        assert_eq!(
+            ErrorKind::MissingRequiredArgument,
+            result.unwrap_err().kind()
+        );
+    }
+}
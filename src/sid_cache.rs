@@ -0,0 +1,273 @@
+use ring::digest;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use zeroize::Zeroizing;
+
+use crate::session::DerivedKey;
+
+/// The FRITZ!Box invalidates a SID after 20 minutes without requests.
+pub const SID_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// Stores one SID per FRITZ!Box and user in a cache directory.
+///
+/// Each entry holds the SID and the time it was last used, so that SIDs
+/// which the FRITZ!Box has already invalidated are skipped without a request.
+/// Optionally, the cache also keeps the [`DerivedKey`] of the user.
+#[derive(Debug, Clone)]
+pub struct SidCache {
+    directory: PathBuf,
+}
+
+impl SidCache {
+    pub fn new(directory: PathBuf) -> SidCache {
+        SidCache { directory }
+    }
+
+    /// `$XDG_CACHE_HOME/fritzer`, falling back to `~/.cache/fritzer`.
+    pub fn default_directory() -> Option<PathBuf> {
+        let cache_home = match env::var_os("XDG_CACHE_HOME") {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+
+        Some(cache_home.join("fritzer"))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn path(&self, url: &Url, username: &str) -> PathBuf {
+        self.entry_path(url, username, "sid")
+    }
+
+    pub fn derived_key_path(&self, url: &Url, username: &str) -> PathBuf {
+        self.entry_path(url, username, "key")
+    }
+
+    fn entry_path(&self, url: &Url, username: &str, extension: &str) -> PathBuf {
+        let key = format!("{}\n{}", url.as_str(), username);
+        let hash = digest::digest(&digest::SHA256, key.as_bytes());
+
+        self.directory.join(format!(
+            "{}.{}",
+            hex::encode(&hash.as_ref()[..16]),
+            extension
+        ))
+    }
+
+    /// Returns the cached SID unless it is missing, unreadable or older than
+    /// [`SID_TIMEOUT`].
+    pub fn load(&self, url: &Url, username: &str) -> Option<String> {
+        let mut content = String::new();
+
+        File::open(self.path(url, username))
+            .ok()?
+            .read_to_string(&mut content)
+            .ok()?;
+
+        let mut lines = content.lines();
+        let last_used = Duration::from_secs(lines.next()?.parse::<u64>().ok()?);
+        let sid = lines.next()?;
+
+        match now().checked_sub(last_used) {
+            Some(age) if age <= SID_TIMEOUT && !sid.is_empty() => Some(sid.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn store(&self, url: &Url, username: &str, sid: &str) -> Result<(), io::Error> {
+        create_private_directory(&self.directory)?;
+
+        let mut file = create_private_file(&self.path(url, username))?;
+
+        write!(file, "{}\n{}\n", now().as_secs(), sid)
+    }
+
+    pub fn remove(&self, url: &Url, username: &str) -> Result<(), io::Error> {
+        remove_file(&self.path(url, username))
+    }
+
+    pub fn load_derived_key(&self, url: &Url, username: &str) -> Option<DerivedKey> {
+        let content =
+            Zeroizing::new(fs::read_to_string(self.derived_key_path(url, username)).ok()?);
+
+        DerivedKey::decode(&content)
+    }
+
+    pub fn store_derived_key(
+        &self,
+        url: &Url,
+        username: &str,
+        key: &DerivedKey,
+    ) -> Result<(), io::Error> {
+        create_private_directory(&self.directory)?;
+
+        let mut file = create_private_file(&self.derived_key_path(url, username))?;
+
+        writeln!(file, "{}", key.encode().as_str())
+    }
+
+    pub fn remove_derived_key(&self, url: &Url, username: &str) -> Result<(), io::Error> {
+        remove_file(&self.derived_key_path(url, username))
+    }
+}
+
+fn remove_file(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn create_private_directory(path: &Path) -> Result<(), io::Error> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_directory(path: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(path)
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> Result<File, io::Error> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // `mode` only applies to new files, so tighten pre-existing ones as well.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::num::NonZeroU32;
+
+    #[test]
+    fn sid_cache_load_should_return_stored_sid() {
+        // Arrange
+        let directory = tempfile::tempdir().unwrap();
+        let cache = SidCache::new(directory.path().join("fritzer"));
+        let url = Url::parse("http://fritz.box").unwrap();
+
+        cache.store(&url, "user", "0123456789abcdef").unwrap();
+
+        // Act
+        let sid = cache.load(&url, "user");
+
+        // Assert
+        assert_eq!(Some("0123456789abcdef".to_string()), sid);
+    }
+
+    #[test]
+    fn sid_cache_should_key_entries_by_url_and_username() {
+        // Arrange
+        let directory = tempfile::tempdir().unwrap();
+        let cache = SidCache::new(directory.path().to_path_buf());
+        let url1 = Url::parse("http://fritz.box").unwrap();
+        let url2 = Url::parse("http://192.168.178.2").unwrap();
+
+        cache.store(&url1, "user", "1111111111111111").unwrap();
+        cache.store(&url2, "user", "2222222222222222").unwrap();
+
+        // Act
+        let sid1 = cache.load(&url1, "user");
+        let sid2 = cache.load(&url2, "user");
+        let sid3 = cache.load(&url1, "other");
+
+        // Assert
+        assert_eq!(Some("1111111111111111".to_string()), sid1);
+        assert_eq!(Some("2222222222222222".to_string()), sid2);
+        assert_eq!(None, sid3);
+    }
+
+    #[test]
+    fn sid_cache_load_should_skip_stale_sid() {
+        // Arrange
+        let directory = tempfile::tempdir().unwrap();
+        let cache = SidCache::new(directory.path().to_path_buf());
+        let url = Url::parse("http://fritz.box").unwrap();
+        let last_used = now() - SID_TIMEOUT - Duration::from_secs(1);
+
+        fs::write(
+            cache.path(&url, "user"),
+            format!("{}\n0123456789abcdef\n", last_used.as_secs()),
+        )
+        .unwrap();
+
+        // Act
+        let sid = cache.load(&url, "user");
+
+        // Assert
+        assert_eq!(None, sid);
+    }
+
+    #[test]
+    fn sid_cache_load_derived_key_should_return_stored_key() {
+        // Arrange
+        let directory = tempfile::tempdir().unwrap();
+        let cache = SidCache::new(directory.path().to_path_buf());
+        let url = Url::parse("http://fritz.box").unwrap();
+        let key = DerivedKey::derive("password", NonZeroU32::new(10).unwrap(), b"salt");
+
+        cache.store_derived_key(&url, "user", &key).unwrap();
+
+        // Act
+        let result = cache.load_derived_key(&url, "user").unwrap();
+
+        // Assert
+        assert_eq!(key.encode(), result.encode());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sid_cache_store_should_create_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Arrange
+        let directory = tempfile::tempdir().unwrap();
+        let cache = SidCache::new(directory.path().join("fritzer"));
+        let url = Url::parse("http://fritz.box").unwrap();
+
+        // Act
+        cache.store(&url, "user", "0123456789abcdef").unwrap();
+
+        // Assert
+        let metadata = fs::metadata(cache.path(&url, "user")).unwrap();
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+    }
+}
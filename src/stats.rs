@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::FritzerError;
+
+/// Measurements of a device as reported by `getbasicdevicestats`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DeviceStats {
+    /// Degrees Celsius.
+    pub temperature: Vec<Series>,
+    /// Percent.
+    pub humidity: Vec<Series>,
+    /// Volts.
+    pub voltage: Vec<Series>,
+    /// Watts.
+    pub power: Vec<Series>,
+    /// Watt hours.
+    pub energy: Vec<Series>,
+}
+
+/// Measurements at a fixed interval, newest first. Gaps are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Series {
+    /// Seconds between two values.
+    pub grid: u32,
+    pub values: Vec<Option<f64>>,
+}
+
+#[derive(Deserialize)]
+struct RawDeviceStats {
+    temperature: Option<RawStatsList>,
+    humidity: Option<RawStatsList>,
+    voltage: Option<RawStatsList>,
+    power: Option<RawStatsList>,
+    energy: Option<RawStatsList>,
+}
+
+#[derive(Deserialize)]
+struct RawStatsList {
+    #[serde(default)]
+    stats: Vec<RawStats>,
+}
+
+#[derive(Deserialize)]
+struct RawStats {
+    grid: u32,
+    #[serde(rename = "$value", default)]
+    values: String,
+}
+
+/// Parses the response of `getbasicdevicestats`.
+pub fn parse_device_stats(xml: &str) -> Result<DeviceStats, FritzerError> {
+    let raw: RawDeviceStats = serde_xml_rs::from_str(xml)?;
+
+    Ok(DeviceStats {
+        temperature: series(raw.temperature, 10.0)?,
+        humidity: series(raw.humidity, 1.0)?,
+        voltage: series(raw.voltage, 1000.0)?,
+        power: series(raw.power, 100.0)?,
+        energy: series(raw.energy, 1.0)?,
+    })
+}
+
+/// Converts the comma-separated values, given in `1 / divisor` units, where
+/// `-` marks a gap.
+fn series(list: Option<RawStatsList>, divisor: f64) -> Result<Vec<Series>, FritzerError> {
+    let stats = list.map(|list| list.stats).unwrap_or_default();
+
+    stats
+        .into_iter()
+        .map(|stats| {
+            let values = stats
+                .values
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| match value {
+                    "-" => Ok(None),
+                    value => value
+                        .parse::<f64>()
+                        .map(|v| Some(v / divisor))
+                        .map_err(|_| {
+                            FritzerError::InvalidResponse(format!(
+                                "Invalid statistics value '{}'.",
+                                value
+                            ))
+                        }),
+                })
+                .collect::<Result<_, _>>()?;
+
+            Ok(Series {
+                grid: stats.grid,
+                values,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_device_stats_should_scale_values_and_keep_gaps() {
+        // Arrange
+        let xml = r#"<devicestats>
+<temperature><stats count="3" grid="900" datatime="1700000000">220,-,215</stats></temperature>
+<power><stats count="2" grid="10" datatime="1700000000">152000,0</stats></power>
+<energy><stats count="2" grid="2678400">707,650</stats><stats count="1" grid="86400">23</stats></energy>
+</devicestats>"#;
+
+        // Act
+        let stats = parse_device_stats(xml).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![Series {
+                grid: 900,
+                values: vec![Some(22.0), None, Some(21.5)]
+            }],
+            stats.temperature
+        );
+        assert_eq!(vec![Some(1520.0), Some(0.0)], stats.power[0].values);
+        assert_eq!(2, stats.energy.len());
+        assert!(stats.voltage.is_empty());
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ain::Ain;
+use crate::command::Device;
+use crate::error::FritzerError;
+
+/// Names groups of targets, e.g. `kitchen = ["Kitchen *", "087610000434"]`.
+pub type Tags = BTreeMap<String, Vec<String>>;
+
+const TAG_PREFIX: &str = "tag:";
+
+/// A device or a set of devices given on the command line.
+///
+/// - `all`: every device
+/// - `tag:<NAME>`: the targets of a tag
+/// - an AIN in any form accepted by [`Ain`]
+/// - a glob pattern with `*` and `?`, matched case-insensitively against the
+///   device names
+/// - an exact device name, or else the case-insensitive prefix of exactly one
+///   device name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    All,
+    Tag(String),
+    Ain(Ain),
+    Pattern(String),
+    Name(String),
+}
+
+impl Target {
+    /// Whether resolving the target requires the names of the devices.
+    pub fn needs_devices(&self) -> bool {
+        !matches!(self, Target::Ain(_))
+    }
+}
+
+impl FromStr for Target {
+    type Err = Infallible;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        if target == "all" {
+            return Ok(Target::All);
+        }
+
+        if let Some(tag) = target.strip_prefix(TAG_PREFIX) {
+            return Ok(Target::Tag(tag.to_string()));
+        }
+
+        if let Ok(ain) = target.parse() {
+            return Ok(Target::Ain(ain));
+        }
+
+        match target.contains(['*', '?']) {
+            true => Ok(Target::Pattern(target.to_string())),
+            false => Ok(Target::Name(target.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::All => write!(f, "all"),
+            Target::Tag(tag) => write!(f, "{}{}", TAG_PREFIX, tag),
+            Target::Ain(ain) => write!(f, "{}", ain),
+            Target::Pattern(text) | Target::Name(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Resolves `targets` to the AINs of `devices`, without duplicates and in the
+/// order of the targets. Tags are expanded one level deep.
+///
+/// Fails with [`FritzerError::UnknownTarget`] if a target matches no device
+/// and with [`FritzerError::AmbiguousTarget`] if a name or prefix matches
+/// several devices.
+pub fn resolve(
+    targets: &[Target],
+    devices: &[Device],
+    tags: &Tags,
+) -> Result<Vec<Ain>, FritzerError> {
+    let mut ains = Vec::new();
+
+    for target in targets {
+        let resolved = match target {
+            Target::Tag(tag) => {
+                let tag_targets = tags
+                    .get(tag)
+                    .ok_or_else(|| FritzerError::UnknownTarget(target.to_string()))?;
+                let mut resolved = Vec::new();
+
+                for tag_target in tag_targets {
+                    match tag_target.parse::<Target>() {
+                        Ok(Target::Tag(_)) | Err(_) => {
+                            return Err(FritzerError::UnknownTarget(tag_target.clone()));
+                        }
+                        Ok(tag_target) => resolved.extend(resolve_one(&tag_target, devices)?),
+                    }
+                }
+
+                resolved
+            }
+            target => resolve_one(target, devices)?,
+        };
+
+        for ain in resolved {
+            if !ains.contains(&ain) {
+                ains.push(ain);
+            }
+        }
+    }
+
+    Ok(ains)
+}
+
+fn resolve_one(target: &Target, devices: &[Device]) -> Result<Vec<Ain>, FritzerError> {
+    let ains = |devices: Vec<&Device>| devices.into_iter().map(|d| d.ain.clone()).collect();
+
+    match target {
+        Target::All => Ok(ains(devices.iter().collect())),
+        Target::Ain(ain) => Ok(vec![ain.clone()]),
+        Target::Tag(_) => Err(FritzerError::UnknownTarget(target.to_string())),
+        Target::Pattern(pattern) => {
+            let pattern = pattern.to_lowercase();
+            let matches: Vec<&Device> = devices
+                .iter()
+                .filter(|d| matches_glob(&pattern, &d.name.to_lowercase()))
+                .collect();
+
+            match matches.is_empty() {
+                true => Err(FritzerError::UnknownTarget(target.to_string())),
+                false => Ok(ains(matches)),
+            }
+        }
+        Target::Name(name) if name.trim().is_empty() => {
+            Err(FritzerError::UnknownTarget(target.to_string()))
+        }
+        Target::Name(name) => {
+            let lowercase = name.to_lowercase();
+            let candidates = [
+                devices.iter().filter(|d| d.name == *name).collect(),
+                devices
+                    .iter()
+                    .filter(|d| d.name.to_lowercase() == lowercase)
+                    .collect(),
+                devices
+                    .iter()
+                    .filter(|d| d.name.to_lowercase().starts_with(&lowercase))
+                    .collect::<Vec<&Device>>(),
+            ];
+
+            match candidates.into_iter().find(|c| !c.is_empty()) {
+                None => Err(FritzerError::UnknownTarget(target.to_string())),
+                Some(matches) if matches.len() == 1 => Ok(ains(matches)),
+                Some(matches) => Err(FritzerError::AmbiguousTarget(
+                    target.to_string(),
+                    matches
+                        .iter()
+                        .map(|d| format!("{} ({})", d.name, d.ain))
+                        .collect(),
+                )),
+            }
+        }
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any sequence of
+/// characters and `?` exactly one character.
+fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<Device> {
+        [
+            ("087610000001", "Kitchen kettle"),
+            ("087610000002", "Kitchen radio"),
+            ("087610000003", "Office lamp"),
+            ("087610000004", "Office"),
+        ]
+        .iter()
+        .map(|(ain, name)| Device {
+            ain: ain.parse().unwrap(),
+            name: name.to_string(),
+        })
+        .collect()
+    }
+
+    fn targets(targets: &[&str]) -> Vec<Target> {
+        targets.iter().map(|t| t.parse().unwrap()).collect()
+    }
+
+    fn resolve_to_strings(targets: &[Target], tags: &Tags) -> Result<Vec<String>, FritzerError> {
+        resolve(targets, &devices(), tags).map(|ains| {
+            ains.iter()
+                .map(|ain| ain.as_str().to_string())
+                .collect::<Vec<_>>()
+        })
+    }
+
+    #[test]
+    fn resolve_should_accept_ain_name_prefix_and_glob() {
+        // Arrange
+        let targets = targets(&["08761 0000004", "kitchen kettle", "kitchen r", "office *"]);
+
+        // Act
+        let result = resolve_to_strings(&targets, &Tags::new()).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![
+                "087610000004",
+                "087610000001",
+                "087610000002",
+                "087610000003"
+            ],
+            result
+        );
+    }
+
+    #[test]
+    fn resolve_should_prefer_exact_name_over_prefix() {
+        // Arrange
+        let targets = targets(&["Office"]);
+
+        // Act
+        let result = resolve_to_strings(&targets, &Tags::new()).unwrap();
+
+        // Assert
+        assert_eq!(vec!["087610000004"], result);
+    }
+
+    #[test]
+    fn resolve_should_report_candidates_of_ambiguous_prefix() {
+        // Arrange
+        let targets = targets(&["kit"]);
+
+        // Act
+        let result = resolve_to_strings(&targets, &Tags::new());
+
+        // Assert
+        match result {
+            Err(FritzerError::AmbiguousTarget(target, candidates)) => {
+                assert_eq!("kit", target);
+                assert_eq!(
+                    vec![
+                        "Kitchen kettle (08761 0000001)",
+                        "Kitchen radio (08761 0000002)"
+                    ],
+                    candidates
+                );
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn resolve_should_expand_tags_and_all_without_duplicates() {
+        // Arrange
+        let tags = Tags::from([(
+            "morning".to_string(),
+            vec!["Kitchen*".to_string(), "087610000001".to_string()],
+        )]);
+        let targets = targets(&["tag:morning", "all"]);
+
+        // Act
+        let result = resolve_to_strings(&targets, &tags).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![
+                "087610000001",
+                "087610000002",
+                "087610000003",
+                "087610000004"
+            ],
+            result
+        );
+    }
+
+    #[test]
+    fn matches_glob_should_match_wildcards() {
+        // Arrange
+        let cases = [
+            ("office *", "office lamp", true),
+            ("*lamp", "office lamp", true),
+            ("o?fice*", "office", true),
+            ("*kettle", "kitchen radio", false),
+            ("office", "office lamp", false),
+        ];
+
+        // Act
+        let results: Vec<bool> = cases
+            .iter()
+            .map(|(pattern, text, _)| matches_glob(pattern, text))
+            .collect();
+
+        // Assert
+        assert_eq!(
+            cases
+                .iter()
+                .map(|(_, _, expected)| *expected)
+                .collect::<Vec<_>>(),
+            results
+        );
+    }
+}
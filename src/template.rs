@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ain::Ain;
+use crate::error::FritzerError;
+
+/// A template as reported by `gettemplatelistinfos`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Template {
+    pub ain: Ain,
+    pub name: String,
+    /// The devices the template changes.
+    pub devices: Vec<Ain>,
+}
+
+#[derive(Deserialize)]
+struct RawTemplateList {
+    #[serde(default)]
+    template: Vec<RawTemplate>,
+}
+
+#[derive(Deserialize)]
+struct RawTemplate {
+    identifier: String,
+    #[serde(default)]
+    name: String,
+    devices: Option<RawDevices>,
+}
+
+#[derive(Deserialize)]
+struct RawDevices {
+    #[serde(default)]
+    device: Vec<RawDevice>,
+}
+
+#[derive(Deserialize)]
+struct RawDevice {
+    identifier: String,
+}
+
+/// Parses the response of `gettemplatelistinfos`.
+pub fn parse_template_list(xml: &str) -> Result<Vec<Template>, FritzerError> {
+    let list: RawTemplateList = serde_xml_rs::from_str(xml)?;
+
+    list.template
+        .into_iter()
+        .map(|template| {
+            let devices = template.devices.map(|d| d.device).unwrap_or_default();
+
+            Ok(Template {
+                ain: template.identifier.parse()?,
+                name: template.name,
+                devices: devices
+                    .into_iter()
+                    .map(|device| device.identifier.parse())
+                    .collect::<Result<_, _>>()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_template_list_should_parse_templates_and_devices() {
+        // Arrange
+        let xml = r#"<templatelist version="1">
+<template identifier="tmp6F0093-39512EF0F" id="60008" functionbitmask="6784" applymask="1">
+<name>Night</name><metadata></metadata>
+<devices><device identifier="08761 0000434" /><device identifier="09995 0000123" /></devices>
+<triggers></triggers><sub_templates></sub_templates><applymask><relay_automatic /></applymask>
+</template>
+<template identifier="tmp6F0093-39512EF10" id="60009" functionbitmask="6784" applymask="1">
+<name>Empty</name><devices></devices>
+</template>
+</templatelist>"#;
+
+        // Act
+        let templates = parse_template_list(xml).unwrap();
+
+        // Assert
+        assert_eq!(2, templates.len());
+        assert_eq!("tmp6F0093-39512EF0F", templates[0].ain.as_str());
+        assert_eq!("Night", templates[0].name);
+        assert_eq!(
+            vec!["087610000434", "099950000123"],
+            templates[0]
+                .devices
+                .iter()
+                .map(Ain::as_str)
+                .collect::<Vec<_>>()
+        );
+        assert!(templates[1].devices.is_empty());
+    }
+}
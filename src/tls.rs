@@ -0,0 +1,167 @@
+use ring::digest;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ServerName};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::error::FritzerError;
+
+const FINGERPRINT_LENGTH: usize = 32;
+
+/// SHA-256 fingerprint of a DER-encoded certificate.
+///
+/// Parses and displays the colon-separated form shown by browsers and
+/// `openssl x509 -fingerprint -sha256` (`AB:CD:...`). Plain hex is accepted
+/// as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; FINGERPRINT_LENGTH]);
+
+impl Fingerprint {
+    pub fn of(der: &[u8]) -> Fingerprint {
+        let hash = digest::digest(&digest::SHA256, der);
+        let mut fingerprint = [0; FINGERPRINT_LENGTH];
+
+        fingerprint.copy_from_slice(hash.as_ref());
+
+        Fingerprint(fingerprint)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = FritzerError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let digits: String = text.chars().filter(|c| *c != ':').collect();
+        let mut fingerprint = [0; FINGERPRINT_LENGTH];
+
+        hex::decode_to_slice(digits, &mut fingerprint).map_err(|_| {
+            FritzerError::Tls(format!(
+                "Invalid certificate fingerprint '{}'. Expected a SHA-256 fingerprint like 'AB:CD:...'.",
+                text
+            ))
+        })?;
+
+        Ok(Fingerprint(fingerprint))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = hex::encode_upper(self.0);
+        let pairs: Vec<&str> = (0..hex.len()).step_by(2).map(|i| &hex[i..i + 2]).collect();
+
+        write!(f, "{}", pairs.join(":"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fingerprint = String::deserialize(deserializer)?;
+
+        fingerprint.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Accepts exactly the certificate with the pinned fingerprint, regardless of
+/// its issuer, validity or host name. This suits the self-signed certificate
+/// of a FRITZ!Box, which no CA vouches for.
+struct PinnedCertificateVerifier {
+    fingerprint: Fingerprint,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Fingerprint::of(&end_entity.0);
+
+        if fingerprint == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "certificate fingerprint {} does not match pinned fingerprint {}",
+                fingerprint, self.fingerprint
+            )))
+        }
+    }
+}
+
+pub(crate) fn pinned_client_config(fingerprint: Fingerprint) -> rustls::ClientConfig {
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertificateVerifier { fingerprint }))
+        .with_no_client_auth()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str =
+        "2C:F2:4D:BA:5F:B0:A3:0E:26:E8:3B:2A:C5:B9:E2:9E:1B:16:1E:5C:1F:A7:42:5E:73:04:33:62:93:8B:98:24";
+
+    #[test]
+    fn fingerprint_should_round_trip() {
+        // Arrange
+        let hex = FINGERPRINT.replace(':', "").to_lowercase();
+
+        // Act
+        let result = hex.parse::<Fingerprint>().unwrap();
+
+        // Assert
+        assert_eq!(FINGERPRINT, result.to_string());
+        assert_eq!(Fingerprint::of(b"hello"), result);
+    }
+
+    #[test]
+    fn fingerprint_should_reject_wrong_length() {
+        // Arrange
+        let fingerprint = "2C:F2:4D";
+
+        // Act
+        let result = fingerprint.parse::<Fingerprint>();
+
+        // Assert
+        assert!(matches!(result, Err(FritzerError::Tls(_))));
+    }
+
+    #[test]
+    fn pinned_certificate_verifier_should_accept_only_pinned_certificate() {
+        // Arrange
+        let verifier = PinnedCertificateVerifier {
+            fingerprint: FINGERPRINT.parse().unwrap(),
+        };
+        let server_name = ServerName::try_from("fritz.box").unwrap();
+
+        // Act
+        let accepted = verifier.verify_server_cert(
+            &Certificate(b"hello".to_vec()),
+            &[],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            SystemTime::now(),
+        );
+        let rejected = verifier.verify_server_cert(
+            &Certificate(b"other".to_vec()),
+            &[],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            SystemTime::now(),
+        );
+
+        // Assert
+        assert!(accepted.is_ok());
+        assert!(rejected.is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use url::Url;
+
+use crate::error::FritzerError;
+use crate::retry::RetryPolicy;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends HTTP requests to a FRITZ!Box. Paths are resolved relative to the
+/// base path of the FRITZ!Box URL, e.g. `/login_sid.lua` against
+/// `https://gateway.example/fritz/` yields
+/// `https://gateway.example/fritz/login_sid.lua`. Queries and form bodies are
+/// passed on as they are, so they have to be URL-encoded already.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn get(&self, path: &str, query: Option<&str>) -> Result<Response, FritzerError>;
+
+    async fn post_form(&self, path: &str, body: &str) -> Result<Response, FritzerError>;
+
+    /// How the request helpers repeat failed requests. Defaults to no retries.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::NONE
+    }
+}
+
+pub struct ReqwestTransport {
+    url: Url,
+    client: reqwest::Client,
+    retry_policy: RetryPolicy,
+}
+
+impl ReqwestTransport {
+    pub fn new(url: Url) -> ReqwestTransport {
+        ReqwestTransport::with_client(url, reqwest::Client::new())
+    }
+
+    pub fn with_client(url: Url, client: reqwest::Client) -> ReqwestTransport {
+        ReqwestTransport {
+            url: with_base_path(url),
+            client,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> ReqwestTransport {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn resolve(&self, path: &str) -> Result<Url, FritzerError> {
+        self.url
+            .join(path.trim_start_matches('/'))
+            .map_err(|e| FritzerError::InvalidResponse(format!("Invalid command URL: {}", e)))
+    }
+
+    async fn execute(&self, request: reqwest::RequestBuilder) -> Result<Response, FritzerError> {
+        let res = self.client.execute(request.build()?).await?;
+        let status = res.status().as_u16();
+        let body = res.text().await?;
+
+        Ok(Response { status, body })
+    }
+}
+
+/// Ensures that the path of `url` ends with `/`, so that it is kept as the
+/// base path of all commands.
+pub(crate) fn with_base_path(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+
+        url.set_path(&path);
+    }
+
+    url
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn get(&self, path: &str, query: Option<&str>) -> Result<Response, FritzerError> {
+        let mut request_url = self.resolve(path)?;
+
+        if query.is_some() {
+            request_url.set_query(query);
+        }
+
+        self.execute(self.client.get(request_url)).await
+    }
+
+    async fn post_form(&self, path: &str, body: &str) -> Result<Response, FritzerError> {
+        let request_url = self.resolve(path)?;
+        let request = self
+            .client
+            .post(request_url)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.to_string());
+
+        self.execute(request).await
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Answers requests with queued responses and records every request as
+    /// `METHOD path?query` or `METHOD path body`.
+    pub struct FakeTransport {
+        responses: Mutex<VecDeque<Response>>,
+        pub requests: Mutex<Vec<String>>,
+        pub retry_policy: RetryPolicy,
+    }
+
+    impl FakeTransport {
+        pub fn with_responses(responses: Vec<(u16, &str)>) -> FakeTransport {
+            FakeTransport {
+                responses: Mutex::new(
+                    responses
+                        .into_iter()
+                        .map(|(status, body)| Response {
+                            status,
+                            body: body.to_string(),
+                        })
+                        .collect(),
+                ),
+                requests: Mutex::new(Vec::new()),
+                retry_policy: RetryPolicy::NONE,
+            }
+        }
+
+        fn respond(&self, request: String) -> Result<Response, FritzerError> {
+            self.requests.lock().unwrap().push(request);
+
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or_else(|| FritzerError::InvalidResponse("No response queued.".to_string()))
+        }
+    }
+
+    #[async_trait]
+    impl Transport for FakeTransport {
+        async fn get(&self, path: &str, query: Option<&str>) -> Result<Response, FritzerError> {
+            match query {
+                Some(query) => self.respond(format!("GET {}?{}", path, query)),
+                None => self.respond(format!("GET {}", path)),
+            }
+        }
+
+        async fn post_form(&self, path: &str, body: &str) -> Result<Response, FritzerError> {
+            self.respond(format!("POST {} {}", path, body))
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            self.retry_policy
+        }
+    }
+
+    #[test]
+    fn reqwest_transport_resolve_should_use_root_path() {
+        // Arrange
+        let transport = ReqwestTransport::new(Url::parse("http://fritz.box").unwrap());
+
+        // Act
+        let login = transport.resolve("/login_sid.lua?version=2").unwrap();
+        let switch = transport
+            .resolve("/webservices/homeautoswitch.lua")
+            .unwrap();
+
+        // Assert
+        assert_eq!("http://fritz.box/login_sid.lua?version=2", login.as_str());
+        assert_eq!(
+            "http://fritz.box/webservices/homeautoswitch.lua",
+            switch.as_str()
+        );
+    }
+
+    #[test]
+    fn reqwest_transport_resolve_should_keep_sub_path() {
+        // Arrange
+        let urls = [
+            "https://gateway.example/fritz/",
+            "https://gateway.example/fritz",
+        ];
+
+        // Act
+        let results: Vec<_> = urls
+            .iter()
+            .map(|url| {
+                ReqwestTransport::new(Url::parse(url).unwrap())
+                    .resolve("/webservices/homeautoswitch.lua")
+                    .unwrap()
+            })
+            .collect();
+
+        // Assert
+        for result in results {
+            assert_eq!(
+                "https://gateway.example/fritz/webservices/homeautoswitch.lua",
+                result.as_str()
+            );
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use libfritzer::{
+    ain::Ain,
+    command::SwitchAction,
+    device::{DeviceInfo, Setpoint},
+    error::FritzerError,
+    stats::{DeviceStats, Series},
+};
+use log::LevelFilter;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Row, Sparkline, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::ConnectedFritzbox;
+
+const HELP: &str = "↑/↓ select  space toggle  +/- setpoint  ←/→ dim  r refresh  q quit";
+
+/// Results of the requests running in the background.
+enum Message {
+    Devices(Result<Vec<DeviceInfo>, FritzerError>),
+    Stats(Ain, Result<DeviceStats, FritzerError>),
+    Done(Result<(), FritzerError>),
+}
+
+#[derive(Default)]
+struct App {
+    devices: Vec<DeviceInfo>,
+    table: TableState,
+    stats: Option<(Ain, Result<DeviceStats, String>)>,
+    stats_requested: Option<Ain>,
+    status: String,
+}
+
+impl App {
+    fn selected(&self) -> Option<&DeviceInfo> {
+        self.table.selected().and_then(|i| self.devices.get(i))
+    }
+
+    /// Replaces the devices and keeps the selected device selected.
+    fn update_devices(&mut self, devices: Vec<DeviceInfo>) {
+        let selected = self.selected().map(|d| d.ain.clone());
+        let index = selected
+            .and_then(|ain| devices.iter().position(|d| d.ain == ain))
+            .or(if devices.is_empty() { None } else { Some(0) });
+
+        self.devices = devices;
+        self.table.select(index);
+    }
+
+    fn select(&mut self, offset: isize) {
+        if self.devices.is_empty() {
+            return;
+        }
+
+        let last = self.devices.len() - 1;
+        let index = self.table.selected().unwrap_or(0);
+
+        self.table
+            .select(Some(index.saturating_add_signed(offset).min(last)));
+    }
+}
+
+/// The next setpoint in steps of 0.5 °C, turning the thermostat off below
+/// 8 °C and on above 28 °C.
+fn step_setpoint(setpoint: Setpoint, up: bool) -> Setpoint {
+    match (setpoint, up) {
+        (Setpoint::Off, true) => Setpoint::Celsius(8.0),
+        (Setpoint::On, false) => Setpoint::Celsius(28.0),
+        (Setpoint::Celsius(celsius), true) if celsius >= 28.0 => Setpoint::On,
+        (Setpoint::Celsius(celsius), false) if celsius <= 8.0 => Setpoint::Off,
+        (Setpoint::Celsius(celsius), true) => Setpoint::Celsius(celsius + 0.5),
+        (Setpoint::Celsius(celsius), false) => Setpoint::Celsius(celsius - 0.5),
+        (setpoint, _) => setpoint,
+    }
+}
+
+fn step_level(level: Option<u8>, up: bool) -> u8 {
+    let level = level.unwrap_or_default();
+
+    match up {
+        true => level.saturating_add(10).min(100),
+        false => level.saturating_sub(10),
+    }
+}
+
+/// Shows the devices in a full-screen dashboard until the user quits. The
+/// devices are refreshed every `refresh` in the background.
+pub async fn run(fritzbox: Arc<ConnectedFritzbox>, refresh: Duration) -> Result<(), FritzerError> {
+    let mut terminal = ratatui::try_init()?;
+    // Log messages would scribble over the dashboard.
+    let max_level = log::max_level();
+
+    log::set_max_level(LevelFilter::Off);
+
+    let result = event_loop(&mut terminal, fritzbox, refresh).await;
+
+    ratatui::restore();
+    log::set_max_level(max_level);
+
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    fritzbox: Arc<ConnectedFritzbox>,
+    refresh: Duration,
+) -> Result<(), FritzerError> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut events = EventStream::new();
+    let mut ticker = tokio::time::interval(refresh);
+    let mut app = App::default();
+
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        request_stats(&mut app, &fritzbox, &sender);
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        tokio::select! {
+            _ = ticker.tick() => request_devices(&fritzbox, &sender),
+            Some(message) = receiver.recv() => match message {
+                Message::Devices(Ok(devices)) => app.update_devices(devices),
+                Message::Devices(Err(e)) => app.status = e.to_string(),
+                Message::Stats(ain, result) => {
+                    app.stats = Some((ain, result.map_err(|e| e.to_string())));
+                }
+                Message::Done(Ok(())) => request_devices(&fritzbox, &sender),
+                Message::Done(Err(e)) => app.status = e.to_string(),
+            },
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    if !handle_key(key, &mut app, &fritzbox, &sender) {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Returns `false` if the user quits.
+fn handle_key(
+    key: KeyEvent,
+    app: &mut App,
+    fritzbox: &Arc<ConnectedFritzbox>,
+    sender: &UnboundedSender<Message>,
+) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+        KeyCode::Down | KeyCode::Char('j') => app.select(1),
+        KeyCode::Up | KeyCode::Char('k') => app.select(-1),
+        KeyCode::Char('r') => request_devices(fritzbox, sender),
+        _ => {}
+    }
+
+    let Some(device) = app.selected() else {
+        return true;
+    };
+    let ain = device.ain.clone();
+
+    match key.code {
+        KeyCode::Char(' ') | KeyCode::Char('t') if device.switch.is_some() => {
+            spawn(fritzbox, sender, move |fritzbox| async move {
+                fritzbox
+                    .set_switch(&ain, SwitchAction::Toggle)
+                    .await
+                    .map(|_| ())
+            });
+        }
+        KeyCode::Char(key @ ('+' | '-')) => {
+            if let Some(thermostat) = &device.thermostat {
+                let setpoint = step_setpoint(thermostat.target, key == '+');
+
+                spawn(fritzbox, sender, move |fritzbox| async move {
+                    fritzbox.set_thermostat(&ain, setpoint).await
+                });
+            }
+        }
+        KeyCode::Right | KeyCode::Left if device.level.is_some() => {
+            let level = step_level(device.level, key.code == KeyCode::Right);
+
+            spawn(fritzbox, sender, move |fritzbox| async move {
+                fritzbox.set_level(&ain, level).await
+            });
+        }
+        _ => return true,
+    }
+
+    app.status = String::new();
+
+    true
+}
+
+fn spawn<F, Fut>(fritzbox: &Arc<ConnectedFritzbox>, sender: &UnboundedSender<Message>, action: F)
+where
+    F: FnOnce(Arc<ConnectedFritzbox>) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<(), FritzerError>> + Send,
+{
+    let fritzbox = fritzbox.clone();
+    let sender = sender.clone();
+
+    tokio::spawn(async move {
+        let _ = sender.send(Message::Done(action(fritzbox).await));
+    });
+}
+
+fn request_devices(fritzbox: &Arc<ConnectedFritzbox>, sender: &UnboundedSender<Message>) {
+    let fritzbox = fritzbox.clone();
+    let sender = sender.clone();
+
+    tokio::spawn(async move {
+        let _ = sender.send(Message::Devices(fritzbox.get_device_infos().await));
+    });
+}
+
+/// Requests the statistics of the selected device once after selecting it.
+fn request_stats(
+    app: &mut App,
+    fritzbox: &Arc<ConnectedFritzbox>,
+    sender: &UnboundedSender<Message>,
+) {
+    let Some(ain) = app.selected().map(|d| d.ain.clone()) else {
+        return;
+    };
+
+    if app.stats_requested.as_ref() == Some(&ain) {
+        return;
+    }
+
+    app.stats_requested = Some(ain.clone());
+
+    let fritzbox = fritzbox.clone();
+    let sender = sender.clone();
+
+    tokio::spawn(async move {
+        let result = fritzbox.get_device_stats(&ain).await;
+        let _ = sender.send(Message::Stats(ain, result));
+    });
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [table, detail, footer] = Layout::vertical([
+        Constraint::Min(5),
+        Constraint::Length(10),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_table(frame, table, app);
+    draw_detail(frame, detail, app);
+
+    let footer_text = match app.status.is_empty() {
+        true => HELP,
+        false => app.status.as_str(),
+    };
+
+    frame.render_widget(Paragraph::new(footer_text), footer);
+}
+
+fn draw_table(frame: &mut Frame, area: Rect, app: &mut App) {
+    let rows = app.devices.iter().map(|device| {
+        Row::new(vec![
+            device.name.clone(),
+            device.ain.to_string(),
+            match (device.present, device.switch.as_ref().map(|s| s.on)) {
+                (false, _) => "absent".to_string(),
+                (true, Some(Some(true))) => "on".to_string(),
+                (true, Some(Some(false))) => "off".to_string(),
+                (true, Some(None)) => "?".to_string(),
+                (true, None) => String::new(),
+            },
+            optional(device.power_meter.as_ref().map(|m| m.power), |power| {
+                format!("{:.1} W", power)
+            }),
+            optional(device.temperature, |celsius| format!("{:.1} °C", celsius)),
+            optional(device.thermostat.as_ref(), |t| t.target.to_string()),
+            optional(device.level, |level| format!("{} %", level)),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(3),
+            Constraint::Length(16),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(5),
+        ],
+    )
+    .header(
+        Row::new(["Name", "AIN", "State", "Power", "Temp", "Setpoint", "Level"])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+    )
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .block(Block::new().borders(Borders::ALL).title(" fritzer "));
+
+    frame.render_stateful_widget(table, area, &mut app.table);
+}
+
+fn draw_detail(frame: &mut Frame, area: Rect, app: &App) {
+    let block = Block::new().borders(Borders::ALL);
+    let Some(device) = app.selected() else {
+        frame.render_widget(block, area);
+        return;
+    };
+    let block = block.title(format!(" {} ", device.name));
+    let inner = block.inner(area);
+
+    frame.render_widget(block, area);
+
+    let [info, power, temperature] = Layout::horizontal([
+        Constraint::Length(32),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+    ])
+    .areas(inner);
+    let mut lines = vec![
+        Line::from(format!("AIN:     {}", device.ain)),
+        Line::from(format!("Product: {}", device.product_name)),
+    ];
+
+    if let Some(meter) = &device.power_meter {
+        lines.push(Line::from(format!("Energy:  {} Wh", meter.energy)));
+    }
+
+    if let Some(thermostat) = &device.thermostat {
+        lines.push(Line::from(format!(
+            "Current: {}",
+            optional(thermostat.current, |c| format!("{:.1} °C", c))
+        )));
+    }
+
+    frame.render_widget(Paragraph::new(lines), info);
+
+    match &app.stats {
+        Some((ain, Ok(stats))) if *ain == device.ain => {
+            draw_series(frame, power, "Power", stats.power.first());
+            draw_series(frame, temperature, "Temperature", stats.temperature.first());
+        }
+        Some((ain, Err(e))) if *ain == device.ain => {
+            frame.render_widget(Paragraph::new(format!("No statistics: {}", e)), power);
+        }
+        _ => frame.render_widget(Paragraph::new("Loading statistics..."), power),
+    }
+}
+
+/// Draws the oldest to the newest value, shifted so that the minimum is at
+/// the bottom. Gaps are left empty.
+fn draw_series(frame: &mut Frame, area: Rect, title: &str, series: Option<&Series>) {
+    let values: Vec<f64> = series
+        .map(|s| s.values.iter().rev().flatten().copied().collect())
+        .unwrap_or_default();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let data: Vec<u64> = series
+        .map(|s| {
+            s.values
+                .iter()
+                .rev()
+                .map(|v| v.map_or(0, |v| ((v - min) * 10.0) as u64 + 1))
+                .collect()
+        })
+        .unwrap_or_default();
+    let sparkline = Sparkline::default()
+        .block(Block::new().title(title.to_string()))
+        .data(&data[data.len().saturating_sub(area.width as usize)..]);
+
+    frame.render_widget(sparkline, area);
+}
+
+fn optional<T>(value: Option<T>, format: impl Fn(T) -> String) -> String {
+    value.map(format).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libfritzer::device::parse_device_list;
+
+    const DEVICE_LIST: &str = r#"<devicelist version="1">
+<device identifier="08761 0000434" productname="FRITZ!DECT 200"><present>1</present><name>Kettle</name></device>
+<device identifier="08761 0000435" productname="FRITZ!DECT 200"><present>1</present><name>Lamp</name></device>
+</devicelist>"#;
+
+    #[test]
+    fn step_setpoint_should_switch_off_and_on_at_limits() {
+        // Arrange
+        let steps = [
+            (Setpoint::Celsius(21.0), true),
+            (Setpoint::Celsius(8.0), false),
+            (Setpoint::Off, true),
+            (Setpoint::Celsius(28.0), true),
+            (Setpoint::On, true),
+        ];
+
+        // Act
+        let results: Vec<Setpoint> = steps
+            .iter()
+            .map(|(setpoint, up)| step_setpoint(*setpoint, *up))
+            .collect();
+
+        // Assert
+        assert_eq!(
+            vec![
+                Setpoint::Celsius(21.5),
+                Setpoint::Off,
+                Setpoint::Celsius(8.0),
+                Setpoint::On,
+                Setpoint::On
+            ],
+            results
+        );
+    }
+
+    #[test]
+    fn app_update_devices_should_keep_selected_device() {
+        // Arrange
+        let devices = parse_device_list(DEVICE_LIST).unwrap();
+        let mut app = App::default();
+        app.update_devices(devices.clone());
+        app.select(1);
+
+        // Act
+        app.update_devices(devices.into_iter().rev().collect());
+
+        // Assert
+        assert_eq!(Some(0), app.table.selected());
+        assert_eq!("Lamp", app.selected().unwrap().name);
+    }
+}
@@ -0,0 +1,291 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ain::Ain;
+use crate::device::DeviceInfo;
+
+/// The minimum changes of measured values that are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// Watts.
+    pub power: f64,
+    /// Degrees Celsius.
+    pub temperature: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            power: 1.0,
+            temperature: 0.5,
+        }
+    }
+}
+
+/// A change of a device between two snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub ain: Ain,
+    pub name: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The device was added to the FRITZ!Box.
+    Added,
+    /// The device was removed from the FRITZ!Box.
+    Removed,
+    /// The device connected to or disconnected from the FRITZ!Box.
+    Present {
+        present: bool,
+    },
+    Switch {
+        on: Option<bool>,
+    },
+    /// Watts.
+    Power {
+        power: f64,
+        previous: f64,
+    },
+    /// Degrees Celsius.
+    Temperature {
+        temperature: f64,
+        previous: f64,
+    },
+    Alarm {
+        alarm: bool,
+    },
+    ButtonPressed {
+        button: Ain,
+        button_name: String,
+        timestamp: u64,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): ", self.name, self.ain)?;
+
+        match &self.event {
+            Event::Added => write!(f, "added"),
+            Event::Removed => write!(f, "removed"),
+            Event::Present { present: true } => write!(f, "connected"),
+            Event::Present { present: false } => write!(f, "disconnected"),
+            Event::Switch { on: Some(true) } => write!(f, "switched on"),
+            Event::Switch { on: Some(false) } => write!(f, "switched off"),
+            Event::Switch { on: None } => write!(f, "switch state unknown"),
+            Event::Power { power, previous } => {
+                write!(f, "power {:.2} W (was {:.2} W)", power, previous)
+            }
+            Event::Temperature {
+                temperature,
+                previous,
+            } => write!(
+                f,
+                "temperature {:.1} °C (was {:.1} °C)",
+                temperature, previous
+            ),
+            Event::Alarm { alarm: true } => write!(f, "alarm"),
+            Event::Alarm { alarm: false } => write!(f, "alarm cleared"),
+            Event::ButtonPressed { button_name, .. } => {
+                write!(f, "button '{}' pressed", button_name)
+            }
+        }
+    }
+}
+
+/// Compares two snapshots of `getdevicelistinfos` and returns the changes in
+/// the order of `current`, followed by removed devices.
+///
+/// Power and temperature are only reported if they differ from `previous` by
+/// at least the threshold.
+pub fn diff(
+    previous: &[DeviceInfo],
+    current: &[DeviceInfo],
+    thresholds: &Thresholds,
+) -> Vec<Change> {
+    let before: HashMap<&Ain, &DeviceInfo> = previous.iter().map(|d| (&d.ain, d)).collect();
+    let mut changes = Vec::new();
+
+    for device in current {
+        let events = match before.get(&device.ain) {
+            Some(old) => device_events(old, device, thresholds),
+            None => vec![Event::Added],
+        };
+
+        changes.extend(events.into_iter().map(|event| Change {
+            ain: device.ain.clone(),
+            name: device.name.clone(),
+            event,
+        }));
+    }
+
+    let after: HashMap<&Ain, &DeviceInfo> = current.iter().map(|d| (&d.ain, d)).collect();
+
+    changes.extend(
+        previous
+            .iter()
+            .filter(|d| !after.contains_key(&d.ain))
+            .map(|d| Change {
+                ain: d.ain.clone(),
+                name: d.name.clone(),
+                event: Event::Removed,
+            }),
+    );
+
+    changes
+}
+
+fn device_events(old: &DeviceInfo, new: &DeviceInfo, thresholds: &Thresholds) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    if old.present != new.present {
+        events.push(Event::Present {
+            present: new.present,
+        });
+    }
+
+    if let (Some(old), Some(new)) = (&old.switch, &new.switch) {
+        if old.on != new.on {
+            events.push(Event::Switch { on: new.on });
+        }
+    }
+
+    if let (Some(old), Some(new)) = (&old.power_meter, &new.power_meter) {
+        if (new.power - old.power).abs() >= thresholds.power {
+            events.push(Event::Power {
+                power: new.power,
+                previous: old.power,
+            });
+        }
+    }
+
+    if let (Some(old), Some(new)) = (old.temperature, new.temperature) {
+        if (new - old).abs() >= thresholds.temperature {
+            events.push(Event::Temperature {
+                temperature: new,
+                previous: old,
+            });
+        }
+    }
+
+    if let (Some(old), Some(new)) = (old.alarm, new.alarm) {
+        if old != new {
+            events.push(Event::Alarm { alarm: new });
+        }
+    }
+
+    for button in &new.buttons {
+        let last_pressed = old
+            .buttons
+            .iter()
+            .find(|b| b.ain == button.ain)
+            .and_then(|b| b.last_pressed);
+
+        match button.last_pressed {
+            Some(timestamp) if Some(timestamp) > last_pressed => {
+                events.push(Event::ButtonPressed {
+                    button: button.ain.clone(),
+                    button_name: button.name.clone(),
+                    timestamp,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::device::tests::DEVICE_LIST;
+    use crate::device::{parse_device_list, PowerMeter, Switch};
+
+    fn events(changes: &[Change]) -> Vec<(&str, &Event)> {
+        changes.iter().map(|c| (c.ain.as_str(), &c.event)).collect()
+    }
+
+    #[test]
+    fn diff_should_report_changed_functions() {
+        // Arrange
+        let previous = parse_device_list(DEVICE_LIST).unwrap();
+        let mut current = previous.clone();
+        current[0].switch = Some(Switch { on: Some(false) });
+        current[0].power_meter = Some(PowerMeter {
+            power: 0.0,
+            energy: 708,
+            voltage: Some(230.0),
+        });
+        current[0].temperature = Some(22.7);
+        current[2].present = true;
+        current[2].buttons[1].last_pressed = Some(1700000100);
+
+        // Act
+        let changes = diff(&previous, &current, &Thresholds::default());
+
+        // Assert
+        assert_eq!(
+            vec![
+                ("087610000434", &Event::Switch { on: Some(false) }),
+                (
+                    "087610000434",
+                    &Event::Power {
+                        power: 0.0,
+                        previous: 1520.0
+                    }
+                ),
+                ("130960001234", &Event::Present { present: true }),
+                (
+                    "130960001234",
+                    &Event::ButtonPressed {
+                        button: "13096 0001234-3".parse().unwrap(),
+                        button_name: "Hallway: bottom right".to_string(),
+                        timestamp: 1700000100
+                    }
+                ),
+            ],
+            events(&changes)
+        );
+    }
+
+    #[test]
+    fn diff_should_report_added_and_removed_devices() {
+        // Arrange
+        let previous = parse_device_list(DEVICE_LIST).unwrap();
+        let current = previous[1..].to_vec();
+
+        // Act
+        let added = diff(&current, &previous, &Thresholds::default());
+        let removed = diff(&previous, &current, &Thresholds::default());
+
+        // Assert
+        assert_eq!(vec![("087610000434", &Event::Added)], events(&added));
+        assert_eq!(vec![("087610000434", &Event::Removed)], events(&removed));
+    }
+
+    #[test]
+    fn change_should_serialize_event_next_to_device() {
+        // Arrange
+        let change = Change {
+            ain: "087610000434".parse().unwrap(),
+            name: "Kettle".to_string(),
+            event: Event::Switch { on: Some(true) },
+        };
+
+        // Act
+        let json = serde_json::to_string(&change).unwrap();
+
+        // Assert
+        assert_eq!(
+            r#"{"ain":"08761 0000434","name":"Kettle","event":"switch","on":true}"#,
+            json
+        );
+    }
+}